@@ -1,20 +1,49 @@
 
 use std::path;
+use std::path::PathBuf;
 use std::mem;
 use std::io::{Error, ErrorKind};
 
 use crate::empty::Empty;
 use crate::window;
-use crate::filyregex::Command;
+use crate::filyregex;
+use crate::filyregex::{Command, FilyError};
 use crate::filemanager;
+use crate::config::Config;
+use crate::keymap;
+use crate::jobs::{Job, JobState, Jobs, Outcome};
+use crate::jobsview::JobsView;
+use crate::columns;
+use crate::columns::Column;
+use crate::bulkrename::BulkRename;
+use crate::fileops::{Change, FileOp, Policy};
+use crate::journal::Journal;
+use crate::trashbin::TrashBin;
+use crate::keymap::{KeyMap, Lookup};
+use crossterm::event::{KeyCode, KeyEvent};
 
 pub type AppState_t  = Box<AppState>;
+
+const NO_TRASH:&str = "`:restore` and `:purge` need a trash window, open one with `:trash`";
 pub type WinStates = Vec<Box<window::WindowState>>; 
 
+/// Entries yanked or cut in one explorer, waiting to be pasted into another.
+pub struct Clipboard {
+    paths: Vec<PathBuf>,
+    cut: bool,
+}
+
 pub struct AppState{
     counter:i32,
     currWindow: usize,
     windowStates: WinStates,
+    env: filyregex::Environment,
+    status: Option<FilyError>,
+    keymap: KeyMap,
+    journal: Journal,
+    clipboard: Option<Clipboard>,
+    jobs: Jobs,
+    columns: Vec<(Column, u16)>,
     exit: bool,
 }
 
@@ -25,11 +54,56 @@ impl AppState {
             counter:0, 
             currWindow: 0,
             windowStates:Vec::new(),
+            env: filyregex::Environment::new(),
+            status: None,
+            keymap: KeyMap::new(),
+            journal: Journal::new(),
+            clipboard: None,
+            jobs: Jobs::new(),
+            columns: columns::DEFAULT.iter().map(|column| (*column, column.default_width())).collect(),
             exit: false
         };
         state
     }
 
+    /// Runs the `@CORE` expressions of the filyrc and checks the element sections.
+    /// Anything that fails is listed in a message window instead of stopping fily.
+    pub fn startup(&mut self, config:Config, mut messages:Vec<String>) {
+        for (number, src) in &config.core {
+            let commands = filyregex::execute_fily_regex(None, src.clone(), &mut self.env);
+            let (errors, commands):(Vec<Command>, Vec<Command>) = commands.into_iter()
+                .partition(|c| matches!(c, Command::Error(_)));
+
+            self.evaluate_commands(commands);
+            for error in errors {
+                if let Command::Error(e) = error {
+                    messages.push(config_message(&config.origin, *number, &e));
+                }
+            }
+        }
+
+        for (element, lines) in config.elements.iter() {
+            for (number, src) in lines {
+                for command in filyregex::execute_fily_regex(None, src.clone(), &mut self.env) {
+                    match command {
+                        Command::Map(key, body, _) => self.evaluate_commands(vec![Command::Map(key, body, Some(element.clone()))]),
+                        Command::Error(e) => messages.push(config_message(&config.origin, *number, &e)),
+                        _ => messages.push(format!("{}:{}: only key bindings belong in `@CORE_{}`", config.origin, number, element))
+                    }
+                }
+            }
+        }
+
+        if !messages.is_empty() {
+            let win = window::WindowState::new(String::from("filyrc"), window::Element::from(Empty::new(messages.join("\n"))));
+            self.push_win(win);
+        }
+
+        if self.windowStates.is_empty() {
+            self.evaluate_commands(vec![Command::Explorer(None)]);
+        }
+    }
+
     pub fn requests_exit(&self) -> bool {
         return self.exit;
     }
@@ -39,15 +113,29 @@ impl AppState {
         //top level commands
         for command in commands {
             match command {
-                Command::Win(contents) => {
-                    self.push_win(window::WindowState::from(window::Element::from(Empty::new(contents))));
+                Command::Win(contents, title) => {
+                    let mut win = window::WindowState::from(window::Element::from(Empty::new(contents)));
+                    if let Some(title) = title {
+                        win.set_name(title);
+                    }
+                    self.push_win(win);
                 },
-                Command::Quit() => {          
+                Command::Quit(None) => {          
                     self.remove_win(self.curr_win_index());
                 },
-                Command::Explorer() => {
+                Command::Quit(Some(number)) => {
+                    if number > self.windowStates.len() {
+                        self.status = Some(FilyError::new(format!("there is no window {}", number)));
+                        continue;
+                    }
+                    self.remove_win(number - 1);
+                },
+                Command::Explorer(dir) => {
                         let curr_dirs = filemanager::FileManager::get_curr_dir();  // Get the current directories
-                        let file_manager = filemanager::FileManager::new();  // Create a FileManager
+                        let file_manager = match dir {
+                            Some(dir) => filemanager::FileManager::at(dir),
+                            None => filemanager::FileManager::new()  // Create a FileManager
+                        };
                         let element = window::Element::FileManager(Box::new(file_manager));  // Wrap it in an Elemen
                         let win = window::WindowState::new(String::from("balls"), element);
                         self.push_win(win);
@@ -61,6 +149,171 @@ impl AppState {
                     self.push_win(currWin);
 
                 },
+                Command::Eval(src, curr_dir) => {
+                    let marked = self.curr_win().map(|win| win.marked()).unwrap_or_default();
+                    self.env.set(String::from("marked"), filyregex::Value::Paths(marked));
+                    let clip = self.clipboard.as_ref().map(|clipboard| clipboard.paths.clone()).unwrap_or_default();
+                    self.env.set(String::from("clip"), filyregex::Value::Paths(clip));
+                    let filter = self.curr_win().map(|win| win.entry_filter()).unwrap_or_default();
+                    self.env.set_filter(filter);
+                    let commands = filyregex::execute_fily_regex(curr_dir, src, &mut self.env);
+                    self.evaluate_commands(commands);
+                },
+                Command::Vars() => {
+                    let listing:Vec<String> = self.env.vars()
+                        .map(|(name, value)| format!("${} : {} = {}", name, value.kind().name(), value.summary()))
+                        .collect();
+                    let win = window::WindowState::new(String::from("vars"), window::Element::from(Empty::new(listing.join("\n"))));
+                    self.push_win(win);
+                },
+                Command::Error(e) => {
+                    self.status = Some(e);
+                },
+                Command::Map(key, body, element) => {
+                    match keymap::parse_chord(&key) {
+                        Ok(keys) => self.keymap.bind(keys, body, element),
+                        Err(e) => self.status = Some(FilyError::new(e))
+                    }
+                },
+                Command::Maps() => {
+                    let listing:Vec<String> = self.keymap.bindings().into_iter()
+                        .map(|(element, keys, src)| format!("{} {} & |{}|", element.unwrap_or("global"), keys, src))
+                        .collect();
+                    let win = window::WindowState::new(String::from("maps"), window::Element::from(Empty::new(listing.join("\n"))));
+                    self.push_win(win);
+                },
+                Command::FileOp(op, paths) => {
+                    let paths = match self.targets(paths) {
+                        Some(paths) => paths,
+                        None => {
+                            self.status = Some(FilyError::new(format!("`{}` needs a selected entry or piped paths", op.name())));
+                            continue;
+                        }
+                    };
+                    if matches!(op, FileOp::Rename(..)) && paths.len() > 1 {
+                        self.status = Some(FilyError::new("`:rename` renames a single path, use `:mv` to move several"));
+                        continue;
+                    }
+                    self.jobs.spawn(op, paths);
+                },
+                Command::Yank(paths, cut) => {
+                    match self.targets(paths) {
+                        Some(paths) => self.clipboard = Some(Clipboard { paths, cut }),
+                        None => self.status = Some(FilyError::new(format!("`{}` needs a selected entry or piped paths", if cut {":cut"} else {":yank"})))
+                    }
+                },
+                Command::Paste(policy) => {
+                    let dest = match self.curr_win().and_then(|win| win.paste_dir()) {
+                        Some(dest) => dest,
+                        None => {
+                            self.status = Some(FilyError::new("`:paste` needs an explorer window"));
+                            continue;
+                        }
+                    };
+                    // cut entries leave the clipboard once they are moved, see `after_change`
+                    match &self.clipboard {
+                        Some(clipboard) if clipboard.cut => self.jobs.spawn(FileOp::Move(dest, policy), clipboard.paths.clone()),
+                        Some(clipboard) => self.jobs.spawn(FileOp::Copy(dest, policy), clipboard.paths.clone()),
+                        None => self.status = Some(FilyError::new("the clipboard is empty"))
+                    }
+                },
+                Command::BulkRename(paths) => {
+                    // the marked entries, or else everything the explorer lists
+                    let mut paths = paths
+                        .or_else(|| self.curr_win().map(|win| win.marked()).filter(|marked| !marked.is_empty()))
+                        .or_else(|| self.curr_win().map(|win| win.listed()))
+                        .unwrap_or_default();
+                    paths.sort();
+                    paths.dedup();
+                    if paths.is_empty() {
+                        self.status = Some(FilyError::new("`:bulkrename` needs marked entries, an explorer window or piped paths"));
+                        continue;
+                    }
+                    let win = window::WindowState::new(String::from("bulkrename"), window::Element::BulkRename(Box::new(BulkRename::new(paths))));
+                    self.push_win(win);
+                },
+                Command::RenameAll(template, paths, _) => {
+                    let paths = match self.targets(paths) {
+                        Some(paths) => paths,
+                        None => {
+                            self.status = Some(FilyError::new("`:rename` needs a selected entry or piped paths"));
+                            continue;
+                        }
+                    };
+                    // the table of old and new names always comes first, `y` applies it
+                    match template.apply(&paths) {
+                        Ok(names) => {
+                            let bulk = BulkRename::previewing(paths, names);
+                            self.push_win(window::WindowState::new(String::from("rename"), window::Element::BulkRename(Box::new(bulk))));
+                        },
+                        Err(e) => self.status = Some(FilyError::new(format!(":rename: {}", e)))
+                    }
+                },
+                Command::Renames(steps) => {
+                    // the plan is settled once it is applied, how it went shows in `:jobs`
+                    self.jobs.spawn_renames(steps);
+                    self.remove_win(self.currWindow);
+                },
+                Command::Columns(columns) => self.columns = columns,
+                Command::Resize(column, width, by) => {
+                    match self.columns.iter_mut().find(|(other, _)| *other == column) {
+                        Some((_, curr)) if by.is_some() && *curr == 0 => {
+                            self.status = Some(FilyError::new(format!("`{}` fills the room left, give it a width with `:resize {} 20` first", column.name(), column.name())));
+                        },
+                        Some((_, curr)) => *curr = match by {
+                            Some(by) => (*curr as i32).saturating_add(by).clamp(1, columns::MAX_WIDTH as i32) as u16,
+                            None => width
+                        },
+                        None => self.status = Some(FilyError::new(format!("`{}` is not shown, add it with `:columns`", column.name())))
+                    }
+                },
+                Command::Jobs() => {
+                    let win = window::WindowState::new(String::from("jobs"), window::Element::Jobs(Box::new(JobsView::new())));
+                    self.push_win(win);
+                },
+                Command::Cancel(id) => {
+                    if let Err(e) = self.jobs.cancel(id) {
+                        self.status = Some(FilyError::new(e));
+                    }
+                },
+                Command::Trash() => {
+                    let win = window::WindowState::new(String::from("trash"), window::Element::Trash(Box::new(TrashBin::new())));
+                    self.push_win(win);
+                },
+                Command::Undo() => match self.journal.undo() {
+                    Ok(entry) => self.jobs.spawn_undo(entry),
+                    Err(e) => self.status = Some(FilyError::new(e))
+                },
+                Command::Redo() => match self.journal.redo() {
+                    Ok(entry) => self.jobs.spawn_redo(entry),
+                    Err(e) => self.status = Some(FilyError::new(e))
+                },
+                Command::Journal() => {
+                    let listing = if self.journal.history().is_empty() {String::from("no changes yet")} else {self.journal.history().join("\n")};
+                    let win = window::WindowState::new(String::from("journal"), window::Element::from(Empty::new(listing)));
+                    self.push_win(win);
+                },
+                Command::Restore() => self.trash_action(":restore", |bin| bin.restore()),
+                Command::Purge(all) => match self.curr_win().and_then(|win| win.trash_bin()).map(|bin| bin.to_purge(all)) {
+                    Some(Ok(items)) => self.jobs.spawn_purge(items),
+                    Some(Err(e)) => self.status = Some(FilyError::new(e)),
+                    None => self.status = Some(FilyError::new(NO_TRASH))
+                },
+                Command::Mark(marking) => {
+                    if !self.curr_win().is_some_and(|win| win.mark(marking)) {
+                        self.status = Some(FilyError::new("`:mark` needs an explorer window"));
+                    }
+                },
+                Command::Sort(sorting) => {
+                    if !self.curr_win().is_some_and(|win| win.sort(sorting)) {
+                        self.status = Some(FilyError::new("`:sort` needs an explorer window"));
+                    }
+                },
+                Command::Filter(filtering) => {
+                    if !self.curr_win().is_some_and(|win| win.filter(filtering)) {
+                        self.status = Some(FilyError::new("`:filter` needs an explorer window"));
+                    }
+                },
                 Command::FocusLeft() => {self.focus_left();}
                 Command::FocusRight() => {self.focus_right();}
 
@@ -70,6 +323,147 @@ impl AppState {
         }
     }
 
+    /// `paths` when given, otherwise the marked entries or the entry under the cursor
+    /// of the focused window.
+    fn targets(&mut self, paths:Option<Vec<PathBuf>>) -> Option<Vec<PathBuf>> {
+        let paths = paths.or_else(|| self.curr_win().map(|win| win.targets()))?;
+        if paths.is_empty() {None} else {Some(paths)}
+    }
+
+    /// A short description of what is on the clipboard, for the status bar.
+    pub fn clipboard_info(&self) -> Option<String> {
+        let clipboard = self.clipboard.as_ref()?;
+        Some(format!("{} {}", clipboard.paths.len(), if clipboard.cut {"cut"} else {"yanked"}))
+    }
+
+    /// Runs `action` on the focused trash window and journals what it changed.
+    fn trash_action(&mut self, command:&'static str, action:impl FnOnce(&mut TrashBin) -> (Vec<Change>, Result<(), String>)) {
+        let (changes, res) = match self.curr_win().and_then(|win| win.trash_bin()) {
+            Some(bin) => action(bin),
+            None => (Vec::new(), Err(String::from(NO_TRASH)))
+        };
+        self.journal.record(command, changes);
+        self.after_change(res);
+    }
+
+    /// Reports a failed change and refreshes every window, since what is on disk
+    /// may have changed even when part of the command failed.
+    fn after_change(&mut self, res:Result<(), String>) {
+        if let Err(e) = res {
+            self.status = Some(FilyError::new(e));
+        }
+        for win in self.windowStates.iter_mut() {
+            win.refresh();
+        }
+        if let Some(clipboard) = &mut self.clipboard {
+            clipboard.paths.retain(|path| path.symlink_metadata().is_ok());
+            if clipboard.paths.is_empty() {
+                self.clipboard = None;
+            }
+        }
+    }
+
+    /// Journals the jobs that ended since the last call. Called on every turn of the
+    /// event loop.
+    pub fn poll_jobs(&mut self) {
+        for (command, outcome, res) in self.jobs.poll() {
+            match outcome {
+                Outcome::Changed(changes) => self.journal.record(command, changes),
+                Outcome::Undone(left, reversed) => self.journal.undone(left, reversed),
+                Outcome::Redone(left, redone) => self.journal.redone(left, redone)
+            }
+            self.after_change(res);
+        }
+    }
+
+    /// The columns explorers lay their listing out in.
+    pub fn columns(&self) -> &[(Column, u16)] {
+        &self.columns
+    }
+
+    pub fn jobs(&self) -> &[Job] {
+        self.jobs.jobs()
+    }
+
+    /// The progress of the running job, for the status bar.
+    pub fn jobs_info(&self) -> Option<String> {
+        let job = self.jobs.running()?;
+        match job.state {
+            JobState::Running(done, total) => Some(format!("{} {}/{}", job.name, done, total)),
+            _ => None
+        }
+    }
+
+    /// The destination a job is waiting on and the path that would replace it.
+    pub fn conflict(&self) -> Option<(&path::Path, &path::Path)> {
+        self.jobs.conflict()
+    }
+
+    /// Routes a key press through the keymap first and then to the focused window.
+    /// Keys typed into a prompt always go to the window. While a job waits on a
+    /// conflict every key goes to its prompt.
+    pub fn handle_key(&mut self, key:KeyEvent) {
+        if self.jobs.conflict().is_some() {
+            self.answer_conflict(key);
+            return;
+        }
+
+        let (element, curr_dir, typing) = match self.curr_win() {
+            Some(win) => (win.element_name(), win.curr_dir(), win.using_keyboard()),
+            None => return
+        };
+
+        if !typing {
+            match self.keymap.feed(element, key) {
+                Lookup::Run(src) => {
+                    self.evaluate_commands(vec![Command::Eval(src, curr_dir)]);
+                    return;
+                },
+                Lookup::Pending => return,
+                Lookup::Unbound => {}
+            }
+        }
+
+        let commands = match self.curr_win() {
+            Some(win) => win.handle_input(key),
+            None => None
+        };
+        if let Some(commands) = commands {
+            self.evaluate_commands(commands);
+        }
+    }
+
+    /// `o`, `s` and `r` overwrite, skip or rename this one, and `O`, `S` and `R` do
+    /// the same for the rest of the job. `c` and escape cancel the job.
+    fn answer_conflict(&mut self, key:KeyEvent) {
+        let answer = match key.code {
+            KeyCode::Char('o') => Some((Policy::Overwrite, false)),
+            KeyCode::Char('s') => Some((Policy::Skip, false)),
+            KeyCode::Char('r') => Some((Policy::Rename, false)),
+            KeyCode::Char('O') => Some((Policy::Overwrite, true)),
+            KeyCode::Char('S') => Some((Policy::Skip, true)),
+            KeyCode::Char('R') => Some((Policy::Rename, true)),
+            KeyCode::Char('c') | KeyCode::Esc => None,
+            _ => return
+        };
+        self.jobs.answer(answer);
+    }
+
+    /// Shown in the status bar while a chord is only partly typed.
+    pub fn pending_keys(&self) -> Option<String> {
+        self.keymap.pending()
+    }
+
+    /// The last error reported by a fily expression, shown in the status bar until
+    /// the next key press.
+    pub fn status(&self) -> Option<&FilyError> {
+        self.status.as_ref()
+    }
+
+    pub fn clear_status(&mut self) {
+        self.status = None;
+    }
+
     pub fn curr_win_index(&self) -> usize {
         return self.currWindow;
     }
@@ -135,11 +529,16 @@ impl AppState {
     }
 
     pub fn remove_win(&mut self, index:usize) {
+        if index >= self.windowStates.len() {
+            return;
+        }
 
-        self.windowStates[self.currWindow].handle_quit();
+        self.windowStates[index].handle_quit();
         self.windowStates.remove(index);
 
-        self.focus_left();
+        if index <= self.currWindow {
+            self.focus_left();
+        }
     }
 
     fn inc(& mut self) {
@@ -148,4 +547,7 @@ impl AppState {
 
 }
 
-
+/// Message windows center their lines, so the source is quoted rather than underlined.
+fn config_message(origin:&str, number:usize, e:&FilyError) -> String {
+    format!("{}:{}: {} in `{}`", origin, number, e, e.src.trim())
+}
@@ -0,0 +1,113 @@
+use std::fs;
+use std::io::{self, Write};
+
+use crate::filyregex;
+use crate::filyregex::{Command, FilyError};
+use crate::fileops;
+use crate::bulkrename;
+
+const USAGE:&str = "usage: fily [-c <expr> | -f <script>]
+
+  -c <expr>     evaluate a fily expression against the working directory
+  -f <script>   evaluate the fily expressions in a script file
+  -h, --help    show this message
+
+with no arguments fily starts the interactive browser";
+
+/// Runs fily without the TUI for `fily -c '<expr>'` and `fily -f <script>`. Results are
+/// printed to stdout and errors to stderr. Returns the process exit code: 0 on
+/// success, 1 when evaluation fails or a command needs the interactive browser, and
+/// 2 for bad usage or an unreadable script.
+pub fn run(args:Vec<String>) -> i32 {
+    let (src, origin) = match args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        ["-h"] | ["--help"] => {
+            println!("{}", USAGE);
+            return 0;
+        },
+        ["-c", expr] => (String::from(expr), String::from("<expr>")),
+        ["-f", path] => match fs::read_to_string(path) {
+            Ok(src) => (src, String::from(path)),
+            Err(e) => {
+                eprintln!("fily: cannot read {}: {}", path, e);
+                return 2;
+            }
+        },
+        _ => {
+            eprintln!("{}", USAGE);
+            return 2;
+        }
+    };
+
+    let mut env = filyregex::Environment::new();
+    let commands = filyregex::execute_fily_batch(None, src, &mut env);
+    let mut stdout = io::stdout().lock();
+    let mut code = 0;
+
+    for command in commands {
+        let res = match command {
+            Command::Echo(value) => writeln!(stdout, "{}", value.to_text()),
+            Command::Win(contents, _) => writeln!(stdout, "{}", contents),
+            Command::Vars() => env.vars()
+                .try_for_each(|(name, value)| writeln!(stdout, "${} : {} = {}", name, value.kind().name(), value.summary())),
+            Command::FileOp(op, Some(paths)) => {
+                if let (_, Err(e)) = fileops::apply(&op, &paths) {
+                    eprintln!("{}: error: {}", origin, e);
+                    code = 1;
+                }
+                Ok(())
+            },
+            Command::RenameAll(template, Some(paths), dryRun) => {
+                let planned = template.apply(&paths)
+                    .map_err(|e| vec![e])
+                    .and_then(|names| bulkrename::plan(&paths, &names));
+                match planned {
+                    Ok((steps, lines)) => {
+                        let res = lines.iter().try_for_each(|line| writeln!(stdout, "{}", line));
+                        if !dryRun {
+                            if let (_, Err(e)) = bulkrename::apply(&steps) {
+                                eprintln!("{}: error: {}", origin, e);
+                                code = 1;
+                            }
+                        }
+                        res
+                    },
+                    Err(problems) => {
+                        for problem in problems {
+                            eprintln!("{}: error: :rename: {}", origin, problem);
+                        }
+                        code = 1;
+                        Ok(())
+                    }
+                }
+            },
+            Command::Error(e) => {
+                report(&e, &origin);
+                code = 1;
+                Ok(())
+            },
+            command => {
+                eprintln!("{}: error: {} needs the interactive browser, skipped", origin, command.name());
+                code = 1;
+                Ok(())
+            }
+        };
+
+        // a closed pipe such as `fily -c ... | head` is not an error worth reporting
+        if res.is_err() {
+            break;
+        }
+    }
+    code
+}
+
+fn report(e:&FilyError, origin:&str) {
+    let line = e.span.map(|(start, _)| e.src[..start.min(e.src.len())].matches('\n').count() + 1);
+
+    match line {
+        Some(line) => eprintln!("{}:{}: {}", origin, line, e),
+        None => eprintln!("{}: {}", origin, e)
+    }
+    if let Some((src, caret)) = e.caret() {
+        eprintln!("    {}\n    {}", src, caret);
+    }
+}
@@ -0,0 +1,98 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::filyregex::{Args, FilyError, Value, ValueKind};
+
+/// A built-in stage that can sit on the right of a pipe, e.g. `*.rs > cat > :win`.
+pub struct Stage {
+    pub name: &'static str,
+    pub accepts: &'static [ValueKind],
+    pub flags: &'static [&'static str],
+    pub run: fn(Value, &Args, &Path) -> Result<Value, FilyError>,
+}
+
+const TEXT_INPUT:&[ValueKind] = &[ValueKind::Paths, ValueKind::Lines, ValueKind::Str];
+
+pub const STAGES:&[Stage] = &[
+    Stage { name: "cat", accepts: TEXT_INPUT, flags: &[], run: cat },
+    Stage { name: "head", accepts: TEXT_INPUT, flags: &["lines"], run: head },
+    Stage { name: "tail", accepts: TEXT_INPUT, flags: &["lines"], run: tail },
+    Stage { name: "wc", accepts: TEXT_INPUT, flags: &[], run: wc },
+];
+
+pub fn find(name:&str) -> Option<&'static Stage> {
+    STAGES.iter().find(|stage| stage.name == name)
+}
+
+fn read(path:&Path) -> String {
+    match fs::read(path) {
+        Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+        Err(e) => format!("{}: {}", path.display(), e)
+    }
+}
+
+/// The text of each file in a path list, or the input itself as a single text.
+fn texts(input:Value, dir:&Path) -> Vec<(Option<PathBuf>, String)> {
+    match input {
+        Value::Paths(files) => files.into_iter().map(|f| {
+            let f = dir.join(f);
+            let text = read(&f);
+            (Some(f), text)
+        }).collect(),
+        input => vec![(None, input.to_text())]
+    }
+}
+
+fn cat(input:Value, _args:&Args, dir:&Path) -> Result<Value, FilyError> {
+    Ok(Value::Lines(texts(input, dir).into_iter()
+        .flat_map(|(_, text)| text.lines().map(String::from).collect::<Vec<_>>())
+        .collect()))
+}
+
+/// Applies `slice` to the lines of every text, with a `==> name <==` header per file
+/// when several files are read.
+fn slice_lines(input:Value, dir:&Path, slice:impl Fn(Vec<&str>) -> Vec<&str>) -> Value {
+    let texts = texts(input, dir);
+    let headers = texts.len() > 1;
+    let mut out:Vec<String> = Vec::new();
+
+    for (file, text) in &texts {
+        if let (true, Some(file)) = (headers, file) {
+            out.push(format!("==> {} <==", file.display()));
+        }
+        out.extend(slice(text.lines().collect()).into_iter().map(String::from));
+    }
+    Value::Lines(out)
+}
+
+fn head(input:Value, args:&Args, dir:&Path) -> Result<Value, FilyError> {
+    let count = args.flag_num("lines")?.unwrap_or(10);
+    Ok(slice_lines(input, dir, |lines| lines.into_iter().take(count).collect()))
+}
+
+fn tail(input:Value, args:&Args, dir:&Path) -> Result<Value, FilyError> {
+    let count = args.flag_num("lines")?.unwrap_or(10);
+    Ok(slice_lines(input, dir, |lines| {
+        let skip = lines.len().saturating_sub(count);
+        lines.into_iter().skip(skip).collect()
+    }))
+}
+
+/// Line, word and byte counts per file, with a total when several files are read.
+fn wc(input:Value, _args:&Args, dir:&Path) -> Result<Value, FilyError> {
+    let texts = texts(input, dir);
+    let mut rows:Vec<Vec<String>> = Vec::new();
+    let mut total = (0, 0, 0);
+
+    for (file, text) in &texts {
+        let counts = (text.lines().count(), text.split_whitespace().count(), text.len());
+        total = (total.0 + counts.0, total.1 + counts.1, total.2 + counts.2);
+
+        let name = file.as_ref().map(|f| f.display().to_string()).unwrap_or_default();
+        rows.push(vec![counts.0.to_string(), counts.1.to_string(), counts.2.to_string(), name]);
+    }
+
+    if texts.len() > 1 {
+        rows.push(vec![total.0.to_string(), total.1.to_string(), total.2.to_string(), String::from("total")]);
+    }
+    Ok(Value::Table(rows))
+}
@@ -0,0 +1,387 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use crate::appstate::AppState;
+use crate::fileops;
+use crate::fileops::{Ask, Change, FileOp, Policy, Tick};
+use crate::filyregex::{Command, FilyError};
+use crate::prompt::Prompt;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+use ratatui::{
+    layout::{Constraint, Direction, Layout, Rect},
+    style::{Color, Modifier, Style},
+    widgets::{Block, BorderType, Borders, Paragraph},
+    Frame,
+};
+
+/// One step of a bulk rename, in the order it has to run.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Step {
+    Rename(PathBuf, PathBuf),
+    Trash(PathBuf),
+}
+
+/// The steps of a rename with the lines that describe them, or the clashes that stop
+/// it from running.
+pub type Plan = Result<(Vec<Step>, Vec<String>), Vec<String>>;
+
+/// Edits the names of several entries as lines of text, like `vidir`. An emptied
+/// line trashes its entry. Saving with `w` shows what will change, `y` applies it.
+/// Lines hold names lossily, so only lines that were edited count as renames and
+/// names that are not valid UTF-8 cannot be edited.
+#[derive(Clone)]
+pub struct BulkRename {
+    paths:Vec<PathBuf>,
+    names:Vec<String>,
+    lineIndex:usize,
+    cursor:usize,
+    isEditing:bool,
+    preview:Option<Plan>,
+    prompt:Prompt
+}
+
+impl BulkRename {
+
+    pub fn new(paths:Vec<PathBuf>) -> BulkRename {
+        let names = paths.iter().map(|path| file_name(path)).collect();
+        BulkRename {
+            paths,
+            names,
+            lineIndex: 0,
+            cursor: 0,
+            isEditing: false,
+            preview: None,
+            prompt: Prompt::default()
+        }
+    }
+
+    /// Opens on the preview of renaming every path to the name next to it, as
+    /// `:rename` does with a template, `None` keeps a name. `n` goes back to edit
+    /// the names.
+    pub fn previewing(paths:Vec<PathBuf>, names:Vec<Option<String>>) -> BulkRename {
+        let mut bulk = BulkRename::new(paths);
+        bulk.preview = Some(plan(&bulk.paths, &names));
+        for (line, name) in bulk.names.iter_mut().zip(names) {
+            if let Some(name) = name {
+                *line = name;
+            }
+        }
+        bulk
+    }
+
+    /// The new name of every line that was edited, `None` for the rest.
+    fn edited(&self) -> Vec<Option<String>> {
+        self.paths.iter().zip(&self.names)
+            .map(|(path, name)| if *name == file_name(path) {None} else {Some(name.clone())})
+            .collect()
+    }
+
+    /// Typing goes to the buffer while a line is edited or the preview waits for an
+    /// answer, so global bindings stay out of the way.
+    pub fn searching(&self) -> bool {
+        self.prompt.is_open() || self.isEditing || self.preview.is_some()
+    }
+
+    pub fn pulling_info(&self) -> String {
+        if self.isEditing {
+            String::from("editing, enter or esc to stop")
+        } else if let Some(preview) = &self.preview {
+            String::from(if preview.is_ok() {"y to apply, n to keep editing"} else {"any key to keep editing"})
+        } else {
+            self.prompt.text()
+        }
+    }
+
+    pub fn handle_input(&mut self, key:KeyEvent) -> Option<Vec<Command>> {
+        if self.isEditing {
+            self.edit(key);
+            return None;
+        }
+
+        if let Some(preview) = &self.preview {
+            return match (key.code, preview) {
+                (KeyCode::Char('y') | KeyCode::Enter, Ok((steps, _))) => {
+                    let steps = steps.clone();
+                    self.preview = None;
+                    Some(vec![Command::Renames(steps)])
+                },
+                _ => {
+                    self.preview = None;
+                    None
+                }
+            };
+        }
+
+        if self.prompt.takes(&key) {
+            return self.prompt.handle_input(key);
+        }
+        match key.code {
+            KeyCode::Up => {
+                self.lineIndex = self.lineIndex.saturating_sub(1);
+            },
+            KeyCode::Down if self.lineIndex + 1 < self.names.len() => {
+                self.lineIndex += 1;
+            },
+            KeyCode::Char('i') | KeyCode::Enter => {
+                let path = &self.paths[self.lineIndex];
+                if path.file_name().and_then(|name| name.to_str()).is_none() {
+                    let message = format!("`{}` is not valid UTF-8 and cannot be edited, d still trashes it", file_name(path));
+                    return Some(vec![Command::Error(FilyError::new(message))]);
+                }
+                self.isEditing = true;
+                self.cursor = self.names[self.lineIndex].chars().count();
+            },
+            KeyCode::Char('d') => {
+                self.names[self.lineIndex].clear();
+            },
+            KeyCode::Char('u') => {
+                self.names[self.lineIndex] = file_name(&self.paths[self.lineIndex]);
+            },
+            KeyCode::Char('w') => {
+                self.preview = Some(plan(&self.paths, &self.edited()));
+            },
+            _ => {}
+        }
+        None
+    }
+
+    /// Line editing, `cursor` counts characters rather than bytes.
+    fn edit(&mut self, key:KeyEvent) {
+        let line = &mut self.names[self.lineIndex];
+        let at = byte_index(line, self.cursor);
+        match key.code {
+            KeyCode::Char(c) if (key.modifiers - KeyModifiers::SHIFT).is_empty() => {
+                line.insert(at, c);
+                self.cursor += 1;
+            },
+            KeyCode::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                line.remove(byte_index(line, self.cursor));
+            },
+            KeyCode::Delete if self.cursor < line.chars().count() => {
+                line.remove(at);
+            },
+            KeyCode::Left => self.cursor = self.cursor.saturating_sub(1),
+            KeyCode::Right => self.cursor = (self.cursor + 1).min(line.chars().count()),
+            KeyCode::Home => self.cursor = 0,
+            KeyCode::End => self.cursor = line.chars().count(),
+            KeyCode::Enter | KeyCode::Esc => self.isEditing = false,
+            _ => {}
+        }
+    }
+
+    pub fn render(&self, f: &mut Frame, _appState:&AppState, outter:Rect, isFocused: bool) {
+        let title = match &self.preview {
+            Some(Ok((steps, _))) => format!("bulkrename [{} changes, y to apply]", steps.len()),
+            Some(Err(problems)) => format!("bulkrename [{} problems]", problems.len()),
+            None => format!("bulkrename [{} entries]", self.paths.len())
+        };
+        f.render_widget(
+        Block::new()
+            .border_type(BorderType::Rounded)
+            .borders(Borders::ALL)
+            .border_style(Style::default().fg(if isFocused {Color::Blue} else {Color::White}))
+            .title(title),
+        outter);
+
+        let inner = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints(vec![Constraint::Percentage(2), Constraint::Percentage(98)])
+            .split(outter)[1];
+        let rows = (inner.height as usize).saturating_sub(2);
+        let bounds = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(vec![Constraint::Length(1); rows + 1])
+            .split(inner);
+
+        let lines:Vec<(String, Style)> = match &self.preview {
+            Some(Ok((_, lines))) if lines.is_empty() => vec![(String::from("nothing to change"), Style::default().fg(Color::White))],
+            Some(Ok((_, lines))) => lines.iter().map(|line| (line.clone(), Style::default().fg(Color::White))).collect(),
+            Some(Err(problems)) => problems.iter().map(|problem| (problem.clone(), Style::default().fg(Color::Red))).collect(),
+            None => {
+                let start = (self.lineIndex + 1).saturating_sub(rows);
+                for (row, i) in (start..self.names.len()).take(rows).enumerate() {
+                    let original = file_name(&self.paths[i]);
+                    let (text, style) = if self.names[i].is_empty() {
+                        (original, Style::default().fg(Color::Red).add_modifier(Modifier::CROSSED_OUT))
+                    } else if self.names[i] != original {
+                        (self.names[i].clone(), Style::default().fg(Color::Yellow))
+                    } else {
+                        (original, Style::default().fg(Color::White))
+                    };
+                    let style = if i == self.lineIndex && !self.isEditing {style.bg(Color::Blue)} else {style};
+                    f.render_widget(Paragraph::new(text).style(style), bounds[row + 1]);
+
+                    if i == self.lineIndex && self.isEditing && isFocused {
+                        let x = bounds[row + 1].x + self.cursor as u16;
+                        f.set_cursor(x.min(bounds[row + 1].right().saturating_sub(1)), bounds[row + 1].y);
+                    }
+                }
+                return;
+            }
+        };
+        for (row, (text, style)) in lines.into_iter().take(rows).enumerate() {
+            f.render_widget(Paragraph::new(text).style(style), bounds[row + 1]);
+        }
+    }
+
+}
+
+fn byte_index(line:&str, cursor:usize) -> usize {
+    line.char_indices().nth(cursor).map(|(i, _)| i).unwrap_or(line.len())
+}
+
+fn file_name(path:&Path) -> String {
+    path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default()
+}
+
+/// Works out the steps that turn each path into its new name, and a line for every
+/// change to show before they run. `None` keeps a name and an empty one trashes
+/// the entry. Names that clash with each other or with entries that stay are
+/// reported instead. Deletions run first, and renames that form a cycle go through
+/// a temporary name.
+pub fn plan(paths:&[PathBuf], names:&[Option<String>]) -> Plan {
+    let mut problems = Vec::new();
+    let mut table = Vec::new();
+    let mut trashed = Vec::new();
+    let mut moves = Vec::new();
+    for (path, name) in paths.iter().zip(names) {
+        let name = match name {
+            Some(name) => name,
+            None => continue
+        };
+        if name.is_empty() {
+            table.push((file_name(path), String::from("(trash)")));
+            trashed.push(path.clone());
+        } else if name == "." || name == ".." || name.contains('/') {
+            problems.push(format!("`{}` is not a file name, use :mv to move entries", name));
+        } else if path.with_file_name(name) != *path {
+            table.push((file_name(path), name.clone()));
+            moves.push((path.clone(), path.with_file_name(name)));
+        }
+    }
+    let width = table.iter().map(|(old, _)| old.chars().count()).max().unwrap_or(0);
+    let mut lines:Vec<String> = table.into_iter().map(|(old, new)| format!("{:<width$}  ->  {}", old, new, width = width)).collect();
+
+    let mut claimed:BTreeMap<&PathBuf, &PathBuf> = BTreeMap::new();
+    for (from, to) in &moves {
+        if let Some(other) = claimed.insert(to, from) {
+            problems.push(format!("`{}` and `{}` would both become `{}`", file_name(other), file_name(from), file_name(to)));
+        }
+    }
+    let leaving:BTreeSet<&PathBuf> = moves.iter().map(|(from, _)| from).chain(trashed.iter()).collect();
+    for (from, to) in &moves {
+        if to.symlink_metadata().is_ok() && !leaving.contains(to) {
+            problems.push(format!("`{}` would replace `{}`, which is not being renamed", file_name(from), file_name(to)));
+        }
+    }
+    if !problems.is_empty() {
+        return Err(problems);
+    }
+
+    let mut steps:Vec<Step> = trashed.into_iter().map(Step::Trash).collect();
+    let mut pending = moves;
+    while !pending.is_empty() {
+        // a rename is ready once nothing still has to leave its destination
+        match pending.iter().position(|(_, to)| !pending.iter().any(|(from, _)| from == to)) {
+            Some(i) => {
+                let (from, to) = pending.remove(i);
+                steps.push(Step::Rename(from, to));
+            },
+            None => {
+                let (from, to) = pending.remove(0);
+                let temp = (1..).map(|n| from.with_file_name(format!(".{}.fily-{}", file_name(&from), n)))
+                    .find(|temp| temp.symlink_metadata().is_err() && !pending.iter().any(|(_, to)| to == temp))
+                    .unwrap_or_else(|| from.clone());
+                lines.push(format!("{} is part of a cycle, it moves through {} first", file_name(&from), file_name(&temp)));
+                steps.push(Step::Rename(from, temp.clone()));
+                pending.push((temp, to));
+            }
+        }
+    }
+    Ok((steps, lines))
+}
+
+/// Runs the steps of a plan in order and stops at the first failure, returning the
+/// changes made so far so they can be journaled.
+pub fn apply(steps:&[Step]) -> (Vec<Change>, Result<(), String>) {
+    apply_with(steps, &fileops::no_tick, &fileops::no_ask)
+}
+
+pub fn apply_with(steps:&[Step], tick:Tick, ask:Ask) -> (Vec<Change>, Result<(), String>) {
+    let mut changes = Vec::new();
+    for step in steps {
+        let (op, path) = match step {
+            Step::Rename(from, to) => (FileOp::Rename(file_name(to), Policy::Ask), from),
+            Step::Trash(path) => (FileOp::Trash, path)
+        };
+        let (done, res) = fileops::apply_with(&op, std::slice::from_ref(path), tick, ask);
+        changes.extend(done);
+        if res.is_err() {
+            return (changes, res);
+        }
+    }
+    (changes, Ok(()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn scratch(name:&str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("fily-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn files(dir:&Path, names:&[&str]) -> Vec<PathBuf> {
+        names.iter().map(|name| {
+            fs::write(dir.join(name), name).unwrap();
+            dir.join(name)
+        }).collect()
+    }
+
+    fn rename(name:&str) -> Option<String> {
+        Some(String::from(name))
+    }
+
+    #[test]
+    fn swaps_go_through_a_temporary_name() {
+        let dir = scratch("bulk-swap");
+        let paths = files(&dir, &["a", "b"]);
+        let (steps, _) = plan(&paths, &[rename("b"), rename("a")]).unwrap();
+        let temp = dir.join(".a.fily-1");
+        assert_eq!(steps, [
+            Step::Rename(dir.join("a"), temp.clone()),
+            Step::Rename(dir.join("b"), dir.join("a")),
+            Step::Rename(temp, dir.join("b")),
+        ]);
+
+        let (_, res) = apply(&steps);
+        assert!(res.is_ok());
+        assert_eq!(fs::read_to_string(dir.join("a")).unwrap(), "b");
+        assert_eq!(fs::read_to_string(dir.join("b")).unwrap(), "a");
+    }
+
+    #[test]
+    fn chains_rename_the_last_link_first() {
+        let dir = scratch("bulk-chain");
+        let paths = files(&dir, &["a", "b", "c"]);
+        let (steps, _) = plan(&paths, &[rename("b"), rename("d"), None]).unwrap();
+        assert_eq!(steps, [Step::Rename(dir.join("b"), dir.join("d")), Step::Rename(dir.join("a"), dir.join("b"))]);
+        let (steps, _) = plan(&paths, &[None, Some(String::new()), None]).unwrap();
+        assert_eq!(steps, [Step::Trash(dir.join("b"))]);
+    }
+
+    #[test]
+    fn clashes_are_reported_instead_of_planned() {
+        let dir = scratch("bulk-clash");
+        let paths = files(&dir, &["a", "b", "c"]);
+        assert_eq!(plan(&paths, &[rename("x"), rename("x"), None]).unwrap_err().len(), 1);
+        assert_eq!(plan(&paths, &[rename("c"), None, None]).unwrap_err().len(), 1);
+        assert!(plan(&paths, &[rename("../a"), None, None]).is_err());
+    }
+}
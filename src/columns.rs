@@ -0,0 +1,196 @@
+use std::fs::Metadata;
+use chrono::{DateTime, Local};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+/// A column of the explorer listing, chosen and sized with `:columns` and `:resize`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Column {
+    Name,
+    Size,
+    Modified,
+    Permissions,
+    Owner,
+}
+
+/// The columns explorers start with.
+pub const DEFAULT:&[Column] = &[Column::Name, Column::Size, Column::Modified];
+
+pub const NAMES:&str = "name, size, mtime, perms or owner";
+
+/// The widest `:columns` and `:resize` make a column.
+pub const MAX_WIDTH:u16 = 1000;
+
+impl Column {
+    pub fn parse(name:&str) -> Option<Column> {
+        match name {
+            "name" => Some(Column::Name),
+            "size" => Some(Column::Size),
+            "mtime" => Some(Column::Modified),
+            "perms" => Some(Column::Permissions),
+            "owner" => Some(Column::Owner),
+            _ => None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Column::Name => "name",
+            Column::Size => "size",
+            Column::Modified => "mtime",
+            Column::Permissions => "perms",
+            Column::Owner => "owner",
+        }
+    }
+
+    /// A width of 0 takes whatever room the other columns leave.
+    pub fn default_width(&self) -> u16 {
+        match self {
+            Column::Name => 0,
+            Column::Size => 7,
+            Column::Modified => 16,
+            Column::Permissions => 10,
+            Column::Owner => 10,
+        }
+    }
+
+    /// What the column shows for an entry. The name column is filled in by the
+    /// explorer, which knows how to label the entry.
+    pub fn value(&self, meta:Option<&Metadata>) -> String {
+        let meta = match (self, meta) {
+            (Column::Name, _) => return String::new(),
+            (_, Some(meta)) => meta,
+            (_, None) => return String::from("?")
+        };
+        match self {
+            Column::Name => String::new(),
+            Column::Size if meta.is_dir() => String::from("-"),
+            Column::Size => human_size(meta.len()),
+            Column::Modified => match meta.modified() {
+                Ok(time) => DateTime::<Local>::from(time).format("%Y-%m-%d %H:%M").to_string(),
+                Err(_) => String::from("?")
+            },
+            Column::Permissions => permissions(meta),
+            Column::Owner => owner(meta),
+        }
+    }
+}
+
+/// The width of every column once a 0 width column has taken the room left in
+/// `total`. Columns are separated by a space.
+pub fn widths(columns:&[(Column, u16)], total:u16) -> Vec<u16> {
+    let fixed:u32 = columns.iter().map(|(_, width)| *width as u32).sum::<u32>() + columns.len().saturating_sub(1) as u32;
+    let fill = (total as u32).saturating_sub(fixed) as u16;
+    let fills = columns.iter().filter(|(_, width)| *width == 0).count().max(1) as u16;
+    columns.iter().map(|(_, width)| if *width == 0 {fill / fills} else {*width}).collect()
+}
+
+/// Pads or cuts every cell to its width on screen, so wide characters such as the
+/// lock marker take two cells. Sizes line up on the right.
+pub fn row(columns:&[(Column, u16)], cells:&[String], widths:&[u16]) -> String {
+    let mut line = Vec::new();
+    for (((column, _), cell), width) in columns.iter().zip(cells).zip(widths) {
+        let width = *width as usize;
+        let cell = fit(cell, width);
+        let pad = " ".repeat(width.saturating_sub(cell.width()));
+        line.push(match column {
+            Column::Size => pad + &cell,
+            _ => cell + &pad
+        });
+    }
+    line.join(" ")
+}
+
+/// Cuts `cell` to `width` cells, ending it with `~` when something was left out.
+fn fit(cell:&str, width:usize) -> String {
+    if cell.width() <= width {
+        return String::from(cell);
+    }
+    let mut fitted = String::new();
+    let mut used = 0;
+    for c in cell.chars() {
+        let w = c.width().unwrap_or(0);
+        if used + w + 1 > width {
+            break;
+        }
+        fitted.push(c);
+        used += w;
+    }
+    if width > 0 {
+        fitted.push('~');
+    }
+    fitted
+}
+
+/// `1536` becomes `1.5K`, small sizes stay in bytes.
+pub fn human_size(bytes:u64) -> String {
+    const UNITS:[&str; 5] = ["K", "M", "G", "T", "P"];
+    if bytes < 1024 {
+        return format!("{}B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if size < 10.0 {format!("{:.1}{}", size, UNITS[unit])} else {format!("{:.0}{}", size, UNITS[unit])}
+}
+
+#[cfg(unix)]
+fn permissions(meta:&Metadata) -> String {
+    use std::os::unix::fs::PermissionsExt;
+    let mode = meta.permissions().mode();
+    let kind = if meta.is_dir() {'d'} else if meta.file_type().is_symlink() {'l'} else {'-'};
+    let bits:String = (0..9).rev()
+        .map(|bit| if mode & (1 << bit) == 0 {'-'} else {['x', 'w', 'r'][bit % 3]})
+        .collect();
+    format!("{}{}", kind, bits)
+}
+
+#[cfg(not(unix))]
+fn permissions(meta:&Metadata) -> String {
+    String::from(if meta.permissions().readonly() {"readonly"} else {"writable"})
+}
+
+/// The user name from `/etc/passwd`, read once, or the uid when it is not listed.
+#[cfg(unix)]
+fn owner(meta:&Metadata) -> String {
+    use std::collections::HashMap;
+    use std::fs;
+    use std::os::unix::fs::MetadataExt;
+    use std::sync::OnceLock;
+
+    static USERS:OnceLock<HashMap<u32, String>> = OnceLock::new();
+    let users = USERS.get_or_init(|| {
+        fs::read_to_string("/etc/passwd").unwrap_or_default()
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split(':');
+                let name = fields.next()?;
+                let uid = fields.nth(1)?.parse().ok()?;
+                Some((uid, String::from(name)))
+            })
+            .collect()
+    });
+    users.get(&meta.uid()).cloned().unwrap_or_else(|| meta.uid().to_string())
+}
+
+#[cfg(not(unix))]
+fn owner(_meta:&Metadata) -> String {
+    String::from("-")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wide_characters_take_two_cells() {
+        let columns = [(Column::Name, 8), (Column::Size, 4)];
+        let short = row(&columns, &[String::from("a \u{1f512}"), String::from("1B")], &[8, 4]);
+        let long = row(&columns, &[String::from("abcdefg\u{1f512}"), String::from("1B")], &[8, 4]);
+        assert_eq!(short, "a \u{1f512}       1B");
+        assert_eq!(long, "abcdefg~   1B");
+        assert_eq!(short.width(), long.width());
+    }
+}
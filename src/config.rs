@@ -0,0 +1,85 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// Used when there is no filyrc on disk.
+const DEFAULT_FILYRC:&str = include_str!("filyrc");
+
+/// Elements that can have their own `@CORE_<Element>` section.
+pub const ELEMENTS:&[&str] = &["FileManager", "Empty", "Trash", "Jobs", "BulkRename"];
+
+/// A line of fily source from the filyrc, with its 1-based line number.
+pub type ConfigLine = (usize, String);
+
+/// The sections of a filyrc. `@CORE` holds expressions run at startup and global
+/// bindings; `@CORE_<Element>` holds bindings that only apply to that element.
+#[derive(Debug, Default)]
+pub struct Config {
+    pub origin: String,
+    pub core: Vec<ConfigLine>,
+    pub elements: BTreeMap<String, Vec<ConfigLine>>,
+}
+
+/// `$XDG_CONFIG_HOME/fily/filyrc`, falling back to `~/.config/fily/filyrc`.
+pub fn filyrc_path() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config")
+    };
+    Some(base.join("fily").join("filyrc"))
+}
+
+impl Config {
+
+    /// Loads the user's filyrc, or the built in default when there is none. Lines
+    /// that cannot be placed in a section are returned as messages, not failures.
+    pub fn load() -> (Config, Vec<String>) {
+        let path = filyrc_path();
+        match path.as_ref().map(fs::read_to_string) {
+            Some(Ok(src)) => Config::parse(&src, &path.unwrap().to_string_lossy()),
+            Some(Err(e)) if e.kind() != io::ErrorKind::NotFound => {
+                let (config, mut messages) = Config::parse(DEFAULT_FILYRC, "filyrc");
+                messages.insert(0, format!("{}: {}, using the default filyrc", path.unwrap().display(), e));
+                (config, messages)
+            },
+            _ => Config::parse(DEFAULT_FILYRC, "filyrc")
+        }
+    }
+
+    pub fn parse(src:&str, origin:&str) -> (Config, Vec<String>) {
+        let mut config = Config { origin: String::from(origin), ..Config::default() };
+        let mut messages = Vec::new();
+        let mut section:Option<Option<String>> = None;
+
+        for (i, line) in src.lines().enumerate() {
+            let number = i + 1;
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+
+            if let Some(header) = trimmed.strip_prefix('@') {
+                section = match (header, header.strip_prefix("CORE_")) {
+                    ("CORE", _) => Some(None),
+                    (_, Some(element)) if ELEMENTS.contains(&element) => Some(Some(String::from(element))),
+                    _ => {
+                        messages.push(format!("{}:{}: unknown section `@{}`, expected `@CORE` or one of {}", origin, number, header,
+                            ELEMENTS.iter().map(|e| format!("`@CORE_{}`", e)).collect::<Vec<_>>().join(", ")));
+                        None
+                    }
+                };
+                continue;
+            }
+
+            match &section {
+                Some(None) => config.core.push((number, String::from(line))),
+                Some(Some(element)) => config.elements.entry(element.clone()).or_default().push((number, String::from(line))),
+                None => messages.push(format!("{}:{}: `{}` is outside of a section", origin, number, trimmed))
+            }
+        }
+
+        (config, messages)
+    }
+}
@@ -121,7 +121,7 @@ impl Empty {
     }
     
     fn execute_fily_regex(&self) -> Vec<filyregex::Command> {
-        filyregex::execute_fily_regex(None, self.currRegex.clone())
+        vec![Command::Eval(self.currRegex.clone(), None)]
     }
 
     pub fn render(&self,  f: &mut Frame, _appState:&AppState, outter:Rect, isFocused: bool) {
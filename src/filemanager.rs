@@ -1,11 +1,16 @@
-use std::path::PathBuf;
+use std::path::{Component, Path, PathBuf};
+use std::collections::BTreeSet;
 use std::env;
 use std::io; 
 use std::fs;
-use std::fs::metadata;
+use std::fs::{metadata, Metadata};
 use crate::appstate::AppState;
+use crate::columns;
+use crate::columns::Column;
 use crate::filyregex;
-use crate::filyregex::Command;
+use crate::filyregex::{Command, FilyError};
+use crate::sorting::{Sort, Sorting};
+use crate::filter::{Filter, Filtering};
 
 use crossterm::{
     event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEventKind, KeyEvent},
@@ -22,11 +27,41 @@ use ratatui::{
     Frame,
 };
 
+/// Shown next to entries fily is not allowed to read.
+const LOCKED:&str = "\u{1f512}";
+
+/// How `:mark` changes the marked entries of an explorer.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Marking {
+    Toggle,
+    All,
+    Invert,
+    Clear,
+    Add(Vec<PathBuf>),
+}
+
+/// What the listing shows about an entry, read once when the directory is listed
+/// rather than on every render.
+#[derive(Clone)]
+struct Entry {
+    meta:Option<Metadata>,
+    isDir:bool,
+    isLink:bool,
+    problem:Option<&'static str>,
+}
+
+/// Lists a directory. Paths stay `PathBuf`s throughout, so names that are not valid
+/// UTF-8 work like any other and are only shown lossily.
 #[derive(Clone)]
 pub struct FileManager{
-    currDir:String,
-    dirs:Box<Vec<String>>, 
+    currDir:PathBuf,
+    dirs:Box<Vec<PathBuf>>, 
+    entries:Vec<Entry>,
     fileIndex:usize,
+    marked:BTreeSet<PathBuf>,
+    error:Option<String>,
+    sort:Sort,
+    filter:Filter,
     currRegex:String,
     isSearching:bool
 }
@@ -35,73 +70,224 @@ pub struct FileManager{
 impl FileManager {
     
     pub fn new() -> FileManager{
-        let dir = FileManager::get_curr_dir();
+        FileManager::at(FileManager::get_curr_dir())
+    }
 
-        FileManager {
-            currDir:String::from(&dir), 
-            dirs:Box::new(FileManager::get_curr_dirs(String::from(&dir))), 
+    pub fn at(dir:PathBuf) -> FileManager{
+        let mut fm = FileManager {
+            dirs:Box::new(Vec::new()), 
+            entries: Vec::new(),
+            currDir:FileManager::normalize(&dir), 
             fileIndex: 0, 
+            marked: BTreeSet::new(),
+            error: None,
+            sort: Sort::default(),
+            filter: Filter::default(),
             currRegex: String::from(""), 
             isSearching: false
-        }
+        };
+        fm.list(&fm.listed_dir());
+        fm
+
+    }
 
+    pub fn curr_dir(&self) -> PathBuf {
+        self.currDir.clone()
     }
 
     pub fn searching(&self) -> bool {
         return self.isSearching;
     }
 
-    pub fn get_curr_dir() -> String{
-         String::from(env::current_dir()
-            .unwrap()
-            .as_path()
-            .to_str()
-            .unwrap()
-        )
+    pub fn get_curr_dir() -> PathBuf{
+        env::current_dir().unwrap_or_else(|_| PathBuf::from("/"))
     }
 
-    pub fn is_dir(path:String) -> bool {
-        metadata(path).unwrap().is_dir()
+    pub fn is_dir(path:&Path) -> bool {
+        metadata(path).map(|m| m.is_dir()).unwrap_or(false)
     }
 
-    pub fn pulling_info(&self) -> String {
-        self.currRegex.clone()
+    /// The marked entries, or the entry under the cursor when nothing is marked.
+    pub fn targets(&self) -> Vec<PathBuf> {
+        if !self.marked.is_empty() {
+            return self.marked();
+        }
+        self.dirs.get(self.fileIndex).cloned().into_iter().collect()
     }
-    pub fn back(path:String) -> String{
-        let key = '/';
-        let mut newPath = path.clone();
-        newPath.pop();
-        let pieces = newPath.split(key);
-    
-        let mut piecesJoin:Vec<String> = pieces.map(|p| format!("{}{}", p, key)).collect();    
-    
-        if piecesJoin.len() < 2{
-            return path;
+
+    pub fn marked(&self) -> Vec<PathBuf> {
+        self.marked.iter().cloned().collect()
+    }
+
+    pub fn listed(&self) -> Vec<PathBuf> {
+        self.dirs.to_vec()
+    }
+
+    /// Marks survive moving between directories, `All` and `Invert` only touch the
+    /// listed entries.
+    pub fn mark(&mut self, marking:Marking) {
+        match marking {
+            Marking::Toggle => {
+                if let Some(entry) = self.dirs.get(self.fileIndex) {
+                    if !self.marked.remove(entry) {
+                        self.marked.insert(entry.clone());
+                    }
+                }
+            },
+            Marking::All => self.marked.extend(self.dirs.iter().cloned()),
+            Marking::Invert => {
+                for entry in self.dirs.iter() {
+                    if !self.marked.remove(entry) {
+                        self.marked.insert(entry.clone());
+                    }
+                }
+            },
+            Marking::Clear => self.marked.clear(),
+            Marking::Add(paths) => self.marked.extend(paths),
+        }
+    }
+
+    /// Reorders the listing, keeping the cursor on the same entry.
+    pub fn sort(&mut self, sorting:Sorting) {
+        let selected = self.dirs.get(self.fileIndex).cloned();
+        self.sort.change(sorting);
+        let listed = self.dirs.drain(..).zip(self.entries.drain(..)).collect();
+        self.arrange(listed);
+        self.fileIndex = selected.and_then(|selected| self.dirs.iter().position(|entry| *entry == selected)).unwrap_or(0);
+    }
+
+    /// Changes what the listing hides.
+    pub fn filter(&mut self, filtering:Filtering) {
+        let selected = self.dirs.get(self.fileIndex).cloned();
+        self.filter.change(filtering);
+        self.list(&self.listed_dir());
+        self.fileIndex = selected.and_then(|selected| self.dirs.iter().position(|entry| *entry == selected)).unwrap_or(0);
+    }
+
+    pub fn entry_filter(&self) -> Filter {
+        self.filter.clone()
+    }
+
+    /// The directory the listing shows. `currDir` is a file after entering one, the
+    /// listing is then still its parent.
+    pub fn listed_dir(&self) -> PathBuf {
+        if FileManager::is_dir(&self.currDir) {
+            self.currDir.clone()
+        } else {
+            FileManager::back(&self.currDir)
         }
-    
-        piecesJoin.remove(piecesJoin.len()-1);
-        return piecesJoin.into_iter().collect(); 
     }
 
+    /// Re-reads the listing after the filesystem changed. When `currDir` itself is
+    /// gone the window climbs to the nearest parent that still exists.
+    pub fn refresh(&mut self) {
+        while !self.currDir.exists() {
+            let parent = FileManager::back(&self.currDir);
+            if parent == self.currDir {
+                break;
+            }
+            self.currDir = parent;
+        }
 
-    pub fn get_curr_dirs(dir:String) -> Vec<String> {
+        self.list(&self.listed_dir());
+        self.fileIndex = self.fileIndex.min(self.dirs.len().saturating_sub(1));
+        self.marked.retain(|entry| entry.symlink_metadata().is_ok());
+    }
 
-        let mut dirs:Vec<String> = Vec::new();
+    pub fn pulling_info(&self) -> String {
+        self.currRegex.clone()
+    }
+
+    /// The parent of `path` as written, so leaving a symlinked directory returns to
+    /// where the link is rather than to its target's parent. The root is its own parent.
+    pub fn back(path:&Path) -> PathBuf{
+        match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => path.to_path_buf()
+        }
+    }
 
 
-        match fs::read_dir(dir) {
-            Ok(entries) => {
-                for entry in entries {
-                    match entry {
-                        Ok(entry) => dirs.push(String::from(entry.path().to_str().unwrap())),
-                        Err(e) =>eprintln!("Error: {}", e),
+    /// Makes `path` absolute and drops its `.` and `..` components as written, the way
+    /// `back` reads it, so symlinks are left as they are rather than resolved.
+    pub fn normalize(path:&Path) -> PathBuf {
+        let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+        let mut normal = PathBuf::new();
+        for component in path.components() {
+            match component {
+                Component::CurDir => {},
+                Component::ParentDir => {
+                    if matches!(normal.components().next_back(), Some(Component::Normal(_))) {
+                        normal.pop();
                     }
+                },
+                component => normal.push(component)
+            }
+        }
+        normal
+    }
 
-                }
+    /// The entries of `dir`, and how many could not be read. They are skipped rather
+    /// than failing the whole listing.
+    pub fn get_curr_dirs(dir:&Path) -> io::Result<(Vec<PathBuf>, usize)> {
+
+        let mut dirs:Vec<PathBuf> = Vec::new();
+        let mut skipped = 0;
+
+        for entry in fs::read_dir(dir)? {
+            match entry {
+                Ok(entry) => dirs.push(entry.path()),
+                Err(_) => skipped += 1,
             }
-            Err(e) => eprintln!("Error: {}", e),
         }
-        dirs
+        Ok((dirs, skipped))
+    }
+
+    /// Lists `dir` through the window's filter and order.
+    fn list(&mut self, dir:&Path) {
+        let (mut dirs, error) = match FileManager::get_curr_dirs(dir) {
+            Ok((dirs, 0)) => (dirs, None),
+            Ok((dirs, skipped)) => (dirs, Some(format!("{} entries of {} could not be read", skipped, dir.display()))),
+            Err(e) => (Vec::new(), Some(format!("cannot read {}: {}", dir.display(), e)))
+        };
+        self.filter.retain(&mut dirs);
+        let listed = dirs.into_iter().map(|path| {
+            let entry = FileManager::inspect(&path);
+            (path, entry)
+        }).collect();
+        self.arrange(listed);
+        self.error = error;
+    }
+
+    /// Sorts the listing, keeping `dirs` and `entries` in step.
+    fn arrange(&mut self, mut listed:Vec<(PathBuf, Entry)>) {
+        self.sort.apply(&mut listed, |entry| (entry.meta.as_ref(), entry.isLink));
+        let (dirs, entries) = listed.into_iter().unzip();
+        self.dirs = Box::new(dirs);
+        self.entries = entries;
+    }
+
+    /// Why the listing is empty or incomplete, if it is.
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    /// Reads what the listing shows about `path`, with a note when it cannot be used
+    /// as it is. Entries that change afterwards show up on the next refresh.
+    fn inspect(path:&Path) -> Entry {
+        let link = fs::symlink_metadata(path);
+        let target = fs::metadata(path);
+        let problem = match (&link, &target) {
+            (Err(e), _) if e.kind() == io::ErrorKind::NotFound => Some("(gone)"),
+            (Err(_), _) => Some(LOCKED),
+            (Ok(meta), Err(_)) if meta.file_type().is_symlink() => Some("(broken link)"),
+            (Ok(_), Err(_)) => Some(LOCKED),
+            (Ok(_), Ok(target)) if target.is_dir() && fs::read_dir(path).is_err() => Some(LOCKED),
+            _ => None
+        };
+        let isDir = target.as_ref().is_ok_and(|meta| meta.is_dir());
+        let isLink = link.as_ref().is_ok_and(|meta| meta.file_type().is_symlink());
+        Entry { meta: target.or(link).ok(), isDir, isLink, problem }
     }
     
     pub fn handle_input(&mut self, key:KeyEvent) -> Option<Vec<Command>>{
@@ -121,12 +307,12 @@ impl FileManager {
             },
             KeyCode::Backspace if !self.isSearching => {
                 
-                let currDir = FileManager::back(String::from(&self.currDir));  
+                let currDir = FileManager::back(&self.currDir);  
                 self.currDir = currDir;
                 self.fileIndex = 0;
                 
-                if FileManager::is_dir(self.currDir.clone()){
-                    self.dirs = Box::new(FileManager::get_curr_dirs(String::from(&self.currDir))); 
+                if FileManager::is_dir(&self.currDir){
+                    self.list(&self.currDir.clone()); 
                 }
 
             },
@@ -137,12 +323,18 @@ impl FileManager {
             KeyCode::Enter if self.fileIndex < self.dirs.len() && !self.isSearching => {
                 
 
-                self.currDir = String::from(&self.dirs[self.fileIndex]); 
+                let entry = self.dirs[self.fileIndex].clone(); 
                 
-                if FileManager::is_dir(self.currDir.clone()){
-
-                    self.dirs = Box::new(FileManager::get_curr_dirs(String::from(&self.currDir))); 
+                if FileManager::is_dir(&entry){
+                    // stay put rather than show an empty listing for a directory we cannot read
+                    if let Err(e) = fs::read_dir(&entry) {
+                        return Some(vec![Command::Error(FilyError::new(format!("cannot open {}: {}", entry.display(), e)))]);
+                    }
+                    self.list(&entry); 
+                } else if entry.symlink_metadata().is_err() {
+                    return Some(vec![Command::Error(FilyError::new(format!("{} no longer exists", entry.display())))]);
                 }
+                self.currDir = FileManager::normalize(&entry);
                 self.fileIndex = 0;
 
             }
@@ -177,11 +369,45 @@ impl FileManager {
     }
 
     fn execute_fily_regex(&self) -> Vec<filyregex::Command> {
-        filyregex::execute_fily_regex(Some(self.currDir.clone()), self.currRegex.clone())
+        vec![Command::Eval(self.currRegex.clone(), Some(self.currDir.clone()))]
+    }
+
+    /// The directory with what is hidden, the order and the marks.
+    fn title(&self) -> String {
+        let mut title = format!("{}", self.currDir.display());
+        if let Some(filter) = self.filter.describe() {
+            title.push_str(&format!(" [{}]", filter));
+        }
+        if let Some(sort) = self.sort.describe() {
+            title.push_str(&format!(" [{}]", sort));
+        }
+        if !self.marked.is_empty() {
+            title.push_str(&format!(" [{} marked]", self.marked.len()));
+        }
+        title
+    }
 
+    /// The entry's name in the listed directory, with a note when it cannot be used.
+    fn label(path:&Path, entry:&Entry) -> String {
+        let mut label = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_else(|| path.to_string_lossy().into_owned());
+        if entry.isDir {
+            label.push('/');
+        }
+        if let Some(problem) = entry.problem {
+            label = format!("{} {}", label, problem);
+        }
+        label
+    }
+
+    /// One line of the listing laid out in `columns`.
+    fn row(path:&Path, entry:&Entry, columns:&[(Column, u16)], widths:&[u16]) -> String {
+        let cells:Vec<String> = columns.iter()
+            .map(|(column, _)| if *column == Column::Name {FileManager::label(path, entry)} else {column.value(entry.meta.as_ref())})
+            .collect();
+        columns::row(columns, &cells, widths)
     }
 
-    pub fn render(&self,  f: &mut Frame, _appState:&AppState, outter:Rect, isFocused: bool) {
+    pub fn render(&self,  f: &mut Frame, appState:&AppState, outter:Rect, isFocused: bool) {
     
         let mut constraints = vec![];
         let pad = 4; 
@@ -207,7 +433,7 @@ impl FileManager {
             .border_type(BorderType::Rounded)    
             .borders(Borders::ALL)
             .border_style(Style::default().fg(if isFocused {Color::Blue} else {Color::White}))
-            .title(format!("{}", self.currDir)),
+            .title(self.title()),
         outter);
         
         
@@ -227,6 +453,16 @@ impl FileManager {
 
     
         
+        let columns = appState.columns();
+        let widths = columns::widths(columns, filesBounds[0].width.saturating_sub(2));
+        // the header takes the spare line under the border when there is one
+        if filesBounds[0].height > 1 {
+            let header:Vec<String> = columns.iter().map(|(column, _)| String::from(column.name())).collect();
+            f.render_widget(Paragraph::new(columns::row(columns, &header, &widths))
+                .style(Style::default().fg(Color::DarkGray).add_modifier(Modifier::BOLD)),
+                Rect { y: filesBounds[0].y + 1, height: 1, ..filesBounds[0] });
+        }
+
         let mut c:usize = 0;
 
         let start = if (self.fileIndex as i32) - (bot as i32) <= 9 {0} else {self.fileIndex - bot};
@@ -237,12 +473,14 @@ impl FileManager {
                 break;
             }
 
-            let currDir = self.dirs[i].clone(); 
+            let currDir = &self.dirs[i]; 
+            let entry = &self.entries[i];
+            let isMarked = self.marked.contains(currDir);
+            let label = FileManager::row(currDir, entry, columns, &widths);
    
             if i == self.fileIndex {
-                let p = Paragraph::new(currDir.clone())
-                .style(Style::default().bg(Color::Blue).fg(Color::Red))
-                .alignment(Alignment::Center);
+                let p = Paragraph::new(label)
+                .style(Style::default().bg(Color::Blue).fg(if isMarked {Color::Yellow} else {Color::Red}));
                 f.render_widget(p, filesBounds[c]);
                 continue;
             }
@@ -251,9 +489,15 @@ impl FileManager {
                 break;
             }
 
-            let p = Paragraph::new(currDir.clone())
-                .style(Style::default().fg(if !FileManager::is_dir(currDir.clone()) {Color::Red} else {Color::Blue}))
-                .alignment(Alignment::Center);
+            let style = if isMarked {
+                Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD)
+            } else if entry.problem.is_some() {
+                Style::default().fg(Color::DarkGray)
+            } else {
+                Style::default().fg(if !entry.isDir {Color::Red} else {Color::Blue})
+            };
+            let p = Paragraph::new(label)
+                .style(style);
             f.render_widget(p, filesBounds[c]);
 
         }
@@ -263,3 +507,99 @@ impl FileManager {
 }
 
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::event::KeyModifiers;
+
+    fn scratch(name:&str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("fily-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn press(fm:&mut FileManager, code:KeyCode) {
+        fm.handle_input(KeyEvent::new(code, KeyModifiers::NONE));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn lists_and_enters_non_utf8_names() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = scratch("non-utf8");
+        let file = dir.join(OsStr::from_bytes(b"caf\xe9.txt"));
+        let sub = dir.join(OsStr::from_bytes(b"d\xffir"));
+        fs::write(&file, "").unwrap();
+        fs::create_dir(&sub).unwrap();
+
+        let mut fm = FileManager::at(dir.clone());
+        let mut listed = fm.listed();
+        listed.sort();
+        assert_eq!(listed, vec![file.clone(), sub.clone()]);
+
+        fm.fileIndex = fm.dirs.iter().position(|entry| *entry == file).unwrap();
+        fm.mark(Marking::Toggle);
+        assert_eq!(fm.targets(), vec![file.clone()]);
+
+        fm.fileIndex = fm.dirs.iter().position(|entry| *entry == sub).unwrap();
+        press(&mut fm, KeyCode::Enter);
+        assert_eq!(fm.curr_dir(), sub);
+        assert!(fm.listed().is_empty());
+
+        press(&mut fm, KeyCode::Backspace);
+        assert_eq!(fm.curr_dir(), dir);
+        assert_eq!(fm.listed().len(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn back_stops_at_the_root() {
+        assert_eq!(FileManager::back(Path::new("/")), PathBuf::from("/"));
+        assert_eq!(FileManager::back(Path::new("/usr/")), PathBuf::from("/"));
+        assert_eq!(FileManager::back(Path::new("/usr/lib/")), PathBuf::from("/usr"));
+
+        let mut fm = FileManager::at(PathBuf::from("/"));
+        assert!(fm.listed().iter().all(|entry| entry.parent() == Some(Path::new("/"))));
+        press(&mut fm, KeyCode::Backspace);
+        assert_eq!(fm.curr_dir(), PathBuf::from("/"));
+        assert_eq!(fm.listed_dir(), PathBuf::from("/"));
+    }
+
+    #[test]
+    fn dot_dot_is_resolved_when_opening() {
+        let dir = scratch("dot-dot");
+        fs::create_dir_all(dir.join("a/b")).unwrap();
+        assert_eq!(FileManager::normalize(Path::new("/..")), PathBuf::from("/"));
+        assert_eq!(FileManager::normalize(Path::new("/usr/./lib/../bin/")), PathBuf::from("/usr/bin"));
+
+        let mut fm = FileManager::at(dir.join("a/b/../."));
+        assert_eq!(fm.curr_dir(), dir.join("a"));
+        assert_eq!(fm.listed(), vec![dir.join("a/b")]);
+
+        press(&mut fm, KeyCode::Backspace);
+        assert_eq!(fm.curr_dir(), dir);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn leaves_a_symlinked_directory_through_the_link() {
+        let dir = scratch("symlink");
+        fs::create_dir_all(dir.join("real/inner")).unwrap();
+        fs::create_dir(dir.join("other")).unwrap();
+        std::os::unix::fs::symlink("../real", dir.join("other/link")).unwrap();
+
+        let mut fm = FileManager::at(dir.join("other"));
+        press(&mut fm, KeyCode::Enter);
+        assert_eq!(fm.curr_dir(), dir.join("other/link"));
+        assert_eq!(fm.listed(), vec![dir.join("other/link/inner")]);
+
+        press(&mut fm, KeyCode::Backspace);
+        assert_eq!(fm.curr_dir(), dir.join("other"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
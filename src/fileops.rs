@@ -0,0 +1,383 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use crate::trash;
+
+/// A change to the filesystem requested by `:cp`, `:mv`, `:rename`, `:rm`, `:mkdir`
+/// or `:touch`. The paths it acts on travel next to it in `Command::FileOp`.
+#[derive(Debug, PartialEq, Clone)]
+pub enum FileOp {
+    Copy(PathBuf, Policy),
+    Move(PathBuf, Policy),
+    Rename(String, Policy),
+    Trash,
+    Remove,
+    MakeDir,
+    Touch,
+}
+
+impl FileOp {
+    pub fn name(&self) -> &'static str {
+        match self {
+            FileOp::Copy(..) => ":cp",
+            FileOp::Move(..) => ":mv",
+            FileOp::Rename(..) => ":rename",
+            FileOp::Trash | FileOp::Remove => ":rm",
+            FileOp::MakeDir => ":mkdir",
+            FileOp::Touch => ":touch",
+        }
+    }
+
+    fn policy(&self) -> Policy {
+        match self {
+            FileOp::Copy(_, policy) | FileOp::Move(_, policy) | FileOp::Rename(_, policy) => *policy,
+            _ => Policy::Ask
+        }
+    }
+}
+
+/// What a copy, move or rename does when its destination already exists.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Policy {
+    Ask,
+    Overwrite,
+    Skip,
+    Rename,
+}
+
+impl Policy {
+    pub fn parse(name:&str) -> Option<Policy> {
+        match name {
+            "ask" => Some(Policy::Ask),
+            "overwrite" => Some(Policy::Overwrite),
+            "skip" => Some(Policy::Skip),
+            "rename" => Some(Policy::Rename),
+            _ => None
+        }
+    }
+}
+
+/// One change a file command made on disk, with what is needed to reverse it.
+/// `Trashed` and `Restored` keep the entry's name in the trash.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Change {
+    Copied(PathBuf, PathBuf),
+    Moved(PathBuf, PathBuf),
+    Trashed(PathBuf, String),
+    Restored(PathBuf, String),
+    Created(PathBuf, bool),
+    Removed(PathBuf),
+    Purged(PathBuf),
+}
+
+/// Called once for every entry a file command touches. An error stops the command,
+/// which is how background jobs are cancelled.
+pub type Tick<'a> = &'a dyn Fn() -> io::Result<()>;
+
+pub fn no_tick() -> io::Result<()> {
+    Ok(())
+}
+
+/// Asked about each conflict while the policy is `Ask`. Returns what to do with this
+/// one and whether to do the same for the rest of the command. An error cancels it.
+pub type Ask<'a> = &'a dyn Fn(&Path, &Path) -> io::Result<(Policy, bool)>;
+
+/// Batch mode has no one to ask.
+pub fn no_ask(_from:&Path, to:&Path) -> io::Result<(Policy, bool)> {
+    Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("`{}` already exists, pass --policy=overwrite, skip or rename", to.display())))
+}
+
+/// The number of ticks a file command will make on `path`: one for every entry of a
+/// copied or deleted tree, one per path otherwise.
+pub fn count(op:&FileOp, path:&Path) -> usize {
+    match (op, fs::symlink_metadata(path)) {
+        (FileOp::Copy(..) | FileOp::Remove, Ok(meta)) if meta.is_dir() => {
+            let entries = match fs::read_dir(path) {
+                Ok(entries) => entries.filter_map(|entry| entry.ok()).map(|entry| count(op, &entry.path())).sum(),
+                Err(_) => 0
+            };
+            entries + 1
+        },
+        _ => 1
+    }
+}
+
+/// Applies `op` to every path in order and stops at the first failure. The changes
+/// made before a failure are returned with it, so they can still be journaled.
+pub fn apply(op:&FileOp, paths:&[PathBuf]) -> (Vec<Change>, Result<(), String>) {
+    apply_with(op, paths, &no_tick, &no_ask)
+}
+
+pub fn apply_with(op:&FileOp, paths:&[PathBuf], tick:Tick, ask:Ask) -> (Vec<Change>, Result<(), String>) {
+    let many = paths.len() > 1;
+    let mut policy = op.policy();
+    let mut changes = Vec::new();
+    for path in paths {
+        if let Err(e) = apply_one(op, path, many, &mut policy, tick, ask, &mut changes) {
+            return (changes, Err(format!("{}: {}: {}", op.name(), path.display(), e)));
+        }
+    }
+    (changes, Ok(()))
+}
+
+fn apply_one(op:&FileOp, path:&Path, many:bool, policy:&mut Policy, tick:Tick, ask:Ask, changes:&mut Vec<Change>) -> io::Result<()> {
+    match op {
+        FileOp::Copy(dest, _) => {
+            let to = match settle(path, target(path, dest, many)?, policy, ask, changes)? {
+                Some(to) => to,
+                None => return tick()
+            };
+            // copies tick for every entry, and one stopped part way is still journaled
+            // so `:undo` can clean it up
+            let res = copy_with(path, &to, tick);
+            if res.is_ok() || to.symlink_metadata().is_ok() {
+                changes.push(Change::Copied(path.to_path_buf(), to));
+            }
+            return res;
+        },
+        FileOp::Move(dest, _) => {
+            if let Some(to) = settle(path, target(path, dest, many)?, policy, ask, changes)? {
+                move_with(path, &to, tick)?;
+                changes.push(Change::Moved(path.to_path_buf(), to));
+            }
+        },
+        FileOp::Rename(name, _) => {
+            if let Some(to) = settle(path, path.with_file_name(name), policy, ask, changes)? {
+                fs::rename(path, &to)?;
+                changes.push(Change::Moved(path.to_path_buf(), to));
+            }
+        },
+        FileOp::Trash => changes.push(Change::Trashed(path.to_path_buf(), trash::put(path)?)),
+        FileOp::Remove => {
+            // deletes tick for every entry on their own
+            remove_with(path, tick)?;
+            changes.push(Change::Removed(path.to_path_buf()));
+            return Ok(());
+        },
+        FileOp::MakeDir => {
+            // only the outermost directory that did not exist yet is new
+            let created = path.ancestors().take_while(|dir| !dir.exists()).last().map(Path::to_path_buf);
+            fs::create_dir_all(path)?;
+            changes.extend(created.map(|dir| Change::Created(dir, true)));
+        },
+        FileOp::Touch => {
+            let created = !path.exists();
+            touch(path)?;
+            if created {
+                changes.push(Change::Created(path.to_path_buf(), false));
+            }
+        },
+    }
+    tick()
+}
+
+/// Decides where `from` goes when `to` already exists, `None` skips it. What gets
+/// overwritten is trashed rather than deleted, so `:undo` can bring it back.
+fn settle(from:&Path, to:PathBuf, policy:&mut Policy, ask:Ask, changes:&mut Vec<Change>) -> io::Result<Option<PathBuf>> {
+    if to.symlink_metadata().is_err() {
+        return Ok(Some(to));
+    }
+    // overwriting trashes `to`, which must not take `from` along with it
+    let holds = match (fs::canonicalize(from), to.parent().map(fs::canonicalize), to.file_name()) {
+        (Ok(from), Some(Ok(parent)), Some(name)) => from.starts_with(parent.join(name)),
+        _ => false
+    };
+
+    let chosen = match *policy {
+        Policy::Ask => {
+            let (chosen, all) = ask(from, &to)?;
+            if all {
+                *policy = chosen;
+            }
+            chosen
+        },
+        chosen => chosen
+    };
+    match chosen {
+        Policy::Overwrite if holds => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("cannot overwrite `{}` with itself or something inside it", to.display()))),
+        Policy::Overwrite => {
+            changes.push(Change::Trashed(to.clone(), trash::put(&to)?));
+            Ok(Some(to))
+        },
+        Policy::Rename => Ok(Some(suffixed(&to))),
+        Policy::Skip | Policy::Ask => Ok(None)
+    }
+}
+
+/// `notes.txt` becomes the first of `notes_1.txt`, `notes_2.txt`, ... that is free.
+fn suffixed(to:&Path) -> PathBuf {
+    let stem = to.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+    let ext = to.extension().map(|ext| format!(".{}", ext.to_string_lossy())).unwrap_or_default();
+    (1..).map(|n| to.with_file_name(format!("{}_{}{}", stem, n, ext)))
+        .find(|path| path.symlink_metadata().is_err())
+        .unwrap_or_else(|| to.to_path_buf())
+}
+
+/// Where `path` ends up when copied or moved to `dest`: inside `dest` when it is a
+/// directory, otherwise `dest` itself. Several paths need a directory to land in.
+fn target(path:&Path, dest:&Path, many:bool) -> io::Result<PathBuf> {
+    let to = match path.file_name() {
+        Some(name) if dest.is_dir() => dest.join(name),
+        _ if many => return Err(io::Error::new(io::ErrorKind::NotADirectory, format!("`{}` is not a directory", dest.display()))),
+        _ => dest.to_path_buf()
+    };
+
+    let inside = match (fs::canonicalize(path), to.parent().map(fs::canonicalize)) {
+        (Ok(from), Some(Ok(parent))) => parent.starts_with(&from),
+        _ => false
+    };
+    if inside {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("cannot put a directory inside itself at `{}`", to.display())));
+    }
+    Ok(to)
+}
+
+/// Refuses to replace anything, including a dangling symlink.
+pub fn free(to:&Path) -> io::Result<()> {
+    match fs::symlink_metadata(to) {
+        Ok(_) => Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("`{}` already exists", to.display()))),
+        Err(_) => Ok(())
+    }
+}
+
+/// Copies a file, symlink or whole directory tree. Symlinks are copied as links and
+/// never followed, so a link back up the tree cannot loop.
+pub fn copy(from:&Path, to:&Path) -> io::Result<()> {
+    copy_with(from, to, &no_tick)
+}
+
+fn copy_with(from:&Path, to:&Path, tick:Tick) -> io::Result<()> {
+    tick()?;
+    let meta = fs::symlink_metadata(from)?;
+    if meta.file_type().is_symlink() {
+        return copy_link(from, to);
+    }
+    if !meta.is_dir() {
+        return fs::copy(from, to).map(|_| ());
+    }
+
+    fs::create_dir(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        copy_with(&entry.path(), &to.join(entry.file_name()), tick)?;
+    }
+    fs::set_permissions(to, meta.permissions())
+}
+
+#[cfg(unix)]
+fn copy_link(from:&Path, to:&Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(fs::read_link(from)?, to)
+}
+
+#[cfg(not(unix))]
+fn copy_link(from:&Path, to:&Path) -> io::Result<()> {
+    fs::copy(from, to).map(|_| ())
+}
+
+/// Renames, falling back to copy and remove when `to` is on another filesystem.
+pub fn move_path(from:&Path, to:&Path) -> io::Result<()> {
+    move_with(from, to, &no_tick)
+}
+
+fn move_with(from:&Path, to:&Path, tick:Tick) -> io::Result<()> {
+    match fs::rename(from, to) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            copy_with(from, to, tick)?;
+            remove_with(from, tick)
+        },
+        res => res
+    }
+}
+
+pub fn remove(path:&Path) -> io::Result<()> {
+    remove_with(path, &no_tick)
+}
+
+/// Deletes a tree entry by entry, so a cancelled delete stops part way.
+fn remove_with(path:&Path, tick:Tick) -> io::Result<()> {
+    tick()?;
+    let meta = fs::symlink_metadata(path)?;
+    if !meta.is_dir() {
+        return fs::remove_file(path);
+    }
+    for entry in fs::read_dir(path)? {
+        remove_with(&entry?.path(), tick)?;
+    }
+    fs::remove_dir(path)
+}
+
+/// Creates an empty file, or bumps the modification time of an existing one.
+pub fn touch(path:&Path) -> io::Result<()> {
+    fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?
+        .set_modified(SystemTime::now())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch(name:&str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("fily-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn overwrite_never_trashes_what_holds_the_source() {
+        let dir = scratch("overwrite-parent");
+        let inner = dir.join("a").join("b");
+        fs::create_dir_all(&inner).unwrap();
+
+        let mut changes = Vec::new();
+        for to in [inner.clone(), dir.join("a")] {
+            assert!(settle(&inner, to, &mut Policy::Overwrite, &no_ask, &mut changes).is_err());
+        }
+        assert!(changes.is_empty());
+        assert!(inner.is_dir());
+    }
+
+    #[test]
+    fn skip_and_rename_leave_the_destination_alone() {
+        let dir = scratch("policies");
+        let from = dir.join("from.txt");
+        let to = dir.join("notes.txt");
+        fs::write(&from, "new").unwrap();
+        fs::write(&to, "old").unwrap();
+        fs::write(dir.join("notes_1.txt"), "").unwrap();
+
+        let mut changes = Vec::new();
+        assert_eq!(settle(&from, dir.join("free.txt"), &mut Policy::Skip, &no_ask, &mut changes).unwrap(), Some(dir.join("free.txt")));
+        assert_eq!(settle(&from, to.clone(), &mut Policy::Skip, &no_ask, &mut changes).unwrap(), None);
+        assert_eq!(settle(&from, to.clone(), &mut Policy::Rename, &no_ask, &mut changes).unwrap(), Some(dir.join("notes_2.txt")));
+        assert!(settle(&from, to.clone(), &mut Policy::Ask, &no_ask, &mut changes).is_err());
+        assert!(changes.is_empty());
+        assert_eq!(fs::read_to_string(&to).unwrap(), "old");
+    }
+
+    #[test]
+    fn an_answer_for_the_rest_is_asked_once() {
+        let dir = scratch("answer-all");
+        let dest = dir.join("dest");
+        fs::create_dir(&dest).unwrap();
+        let paths:Vec<PathBuf> = ["a", "b"].iter().map(|name| dir.join(name)).collect();
+        for path in &paths {
+            fs::write(path, "new").unwrap();
+            fs::write(dest.join(path.file_name().unwrap()), "old").unwrap();
+        }
+
+        let asked = std::cell::Cell::new(0);
+        let ask = |_:&Path, _:&Path| {
+            asked.set(asked.get() + 1);
+            Ok((Policy::Skip, true))
+        };
+        let (changes, res) = apply_with(&FileOp::Copy(dest.clone(), Policy::Ask), &paths, &no_tick, &ask);
+        assert!(res.is_ok() && changes.is_empty());
+        assert_eq!(asked.get(), 1);
+        assert_eq!(fs::read_to_string(dest.join("b")).unwrap(), "old");
+    }
+}
@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use glob::Pattern;
+use ignore::gitignore::Gitignore;
+use ignore::Match;
+
+/// How `:filter` changes what an explorer hides.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Filtering {
+    Dotfiles,
+    Ignored,
+    Glob(Option<Pattern>),
+}
+
+/// Which entries an explorer hides.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Filter {
+    hideDotfiles:bool,
+    hideIgnored:bool,
+    glob:Option<Pattern>,
+}
+
+impl Filter {
+    pub fn change(&mut self, filtering:Filtering) {
+        match filtering {
+            Filtering::Dotfiles => self.hideDotfiles = !self.hideDotfiles,
+            Filtering::Ignored => self.hideIgnored = !self.hideIgnored,
+            Filtering::Glob(glob) => self.glob = glob,
+        }
+    }
+
+    /// What is hidden, for the explorer title.
+    pub fn describe(&self) -> Option<String> {
+        let mut parts = Vec::new();
+        if self.hideDotfiles {
+            parts.push(String::from("no dotfiles"));
+        }
+        if self.hideIgnored {
+            parts.push(String::from("no ignored"));
+        }
+        if let Some(glob) = &self.glob {
+            parts.push(format!("only {}", glob.as_str()));
+        }
+        if parts.is_empty() {None} else {Some(parts.join(", "))}
+    }
+
+    /// The glob only applies to files, so directories stay reachable.
+    pub fn retain(&self, paths:&mut Vec<PathBuf>) {
+        if *self == Filter::default() {
+            return;
+        }
+        let mut ignores:HashMap<PathBuf, Vec<Gitignore>> = HashMap::new();
+        paths.retain(|path| {
+            let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+            let isDir = path.is_dir();
+            if self.hideDotfiles && name.starts_with('.') {
+                return false;
+            }
+            if let Some(glob) = &self.glob {
+                if !isDir && !glob.matches(&name) {
+                    return false;
+                }
+            }
+            if self.hideIgnored {
+                let parent = path.parent().map(Path::to_path_buf).unwrap_or_default();
+                let ignores = ignores.entry(parent).or_insert_with_key(|dir| ignore_files(dir));
+                if name == ".git" || ignored(ignores, path, isDir) {
+                    return false;
+                }
+            }
+            true
+        });
+    }
+}
+
+/// The ignore files that apply to entries of `dir`, deepest first.
+fn ignore_files(dir:&Path) -> Vec<Gitignore> {
+    let root = dir.ancestors().find(|ancestor| ancestor.join(".git").exists());
+    let mut files = Vec::new();
+    for ancestor in dir.ancestors() {
+        files.push(ancestor.join(".ignore"));
+        if root.is_some() {
+            files.push(ancestor.join(".gitignore"));
+        }
+        if Some(ancestor) == root {
+            files.push(ancestor.join(".git").join("info").join("exclude"));
+            break;
+        }
+    }
+    files.into_iter()
+        .filter(|file| file.is_file())
+        .map(|file| {
+            // `exclude` lives under `.git` but speaks for the root of the repository
+            let base = if file.ends_with("info/exclude") {root.map(Path::to_path_buf)} else {file.parent().map(Path::to_path_buf)};
+            let mut builder = ignore::gitignore::GitignoreBuilder::new(base.unwrap_or_default());
+            builder.add(&file);
+            builder.build().unwrap_or_else(|_| Gitignore::empty())
+        })
+        .collect()
+}
+
+/// The deepest file with a rule for `path` decides.
+fn ignored(ignores:&[Gitignore], path:&Path, isDir:bool) -> bool {
+    for gitignore in ignores {
+        match gitignore.matched(path, isDir) {
+            Match::Ignore(_) => return true,
+            Match::Whitelist(_) => return false,
+            Match::None => {}
+        }
+    }
+    false
+}
@@ -1,19 +1,159 @@
 use std::sync::Arc;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::env;
+use std::fmt;
+use glob::{glob_with, MatchOptions, Pattern};
+use crate::builtins;
+use crate::bulkrename::Step;
+use crate::columns;
+use crate::columns::Column;
+use crate::config;
+use crate::fileops::{FileOp, Policy};
+use crate::filemanager::Marking;
+use crate::filter::{Filter, Filtering};
+use crate::sorting;
+use crate::sorting::{SortKey, Sorting};
+use crate::keymap;
+use crate::template;
+use crate::template::Template;
 
 #[derive(Debug, PartialEq)]
 pub enum Command {
-    Win(String),
-    Explorer(),
+    Win(String, Option<String>),
+    Explorer(Option<PathBuf>),
     CopyWin(),
-    Quit(),
+    Quit(Option<usize>),
     FocusLeft(),
     FocusRight(),
     RequestExit(),
-    Map(String, Token),
+    Eval(String, Option<PathBuf>),
+    Vars(),
+    Error(FilyError),
+    Echo(Value),
+    Map(String, String, Option<String>),
+    Maps(),
+    FileOp(FileOp, Option<Vec<PathBuf>>),
+    Mark(Marking),
+    Sort(Sorting),
+    Filter(Filtering),
+    Trash(),
+    Restore(),
+    Purge(bool),
+    Undo(),
+    Redo(),
+    Journal(),
+    Yank(Option<Vec<PathBuf>>, bool),
+    Paste(Policy),
+    BulkRename(Option<Vec<PathBuf>>),
+    Renames(Vec<Step>),
+    RenameAll(Template, Option<Vec<PathBuf>>, bool),
+    Jobs(),
+    Columns(Vec<(Column, u16)>),
+    Resize(Column, u16, Option<i32>),
+    Cancel(Option<usize>),
     NoOp,
     Unknown
 }
 
+impl Command {
+    /// The fily command a `Command` comes from, as it is written in expressions.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Win(..) => ":win",
+            Command::Explorer(_) => ":e",
+            Command::CopyWin() => ":c",
+            Command::Quit(_) => ":q",
+            Command::FocusLeft() => ":lf",
+            Command::FocusRight() => ":rf",
+            Command::RequestExit() => ":exit",
+            Command::Eval(..) => "a key binding",
+            Command::Vars() => ":vars",
+            Command::Error(_) => "an error",
+            Command::Echo(_) => "an expression",
+            Command::Map(..) => ":map",
+            Command::Maps() => ":maps",
+            Command::FileOp(op, _) => op.name(),
+            Command::Mark(_) => ":mark",
+            Command::Sort(_) => ":sort",
+            Command::Filter(_) => ":filter",
+            Command::Trash() => ":trash",
+            Command::Restore() => ":restore",
+            Command::Purge(_) => ":purge",
+            Command::Undo() => ":undo",
+            Command::Redo() => ":redo",
+            Command::Journal() => ":journal",
+            Command::Yank(_, true) => ":cut",
+            Command::Yank(_, false) => ":yank",
+            Command::Paste(_) => ":paste",
+            Command::BulkRename(_) | Command::Renames(_) => ":bulkrename",
+            Command::RenameAll(..) => ":rename",
+            Command::Jobs() => ":jobs",
+            Command::Columns(_) => ":columns",
+            Command::Resize(..) => ":resize",
+            Command::Cancel(_) => ":cancel",
+            Command::NoOp | Command::Unknown => "a command"
+        }
+    }
+}
+
+/// Byte range `start..end` into the source of an expression.
+pub type Span = (usize, usize);
+
+/// An error from lexing, parsing or evaluating a fily expression. `span` points at
+/// the offending part of `src` so it can be underlined in the status bar.
+#[derive(Debug, PartialEq, Clone)]
+pub struct FilyError {
+    pub message: String,
+    pub span: Option<Span>,
+    pub src: String,
+}
+
+impl FilyError {
+    pub fn new(message:impl Into<String>) -> FilyError {
+        FilyError { message: message.into(), span: None, src: String::new() }
+    }
+
+    pub fn at(message:impl Into<String>, span:Span) -> FilyError {
+        FilyError { message: message.into(), span: Some(span), src: String::new() }
+    }
+
+    fn with_src(mut self, src:&str) -> FilyError {
+        self.src = String::from(src);
+        self
+    }
+
+    /// Moves the span right by `offset` bytes, for errors found in a nested source.
+    fn shifted(mut self, offset:usize) -> FilyError {
+        self.span = self.span.map(|(start, end)| (start + offset, end + offset));
+        self
+    }
+
+    /// The source line holding the span and a line of carets underneath it.
+    pub fn caret(&self) -> Option<(String, String)> {
+        let (start, end) = self.span?;
+        let start = start.min(self.src.len());
+        let end = end.clamp(start, self.src.len());
+        if !self.src.is_char_boundary(start) || !self.src.is_char_boundary(end) {
+            return None;
+        }
+
+        let line_start = self.src[..start].rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let line_end = self.src[start..].find('\n').map(|i| start + i).unwrap_or(self.src.len());
+        let line = self.src[line_start..line_end].replace('\t', " ");
+
+        let column = self.src[line_start..start].chars().count();
+        let width = self.src[start..end.min(line_end)].chars().count().max(1);
+        Some((line, format!("{}{}", " ".repeat(column), "^".repeat(width))))
+    }
+}
+
+impl fmt::Display for FilyError {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        write!(f, "error: {}", self.message)
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 enum Token {
     ID(String),
@@ -21,25 +161,53 @@ enum Token {
     Num(f32),
     Extension(Box<Token>),
     Command(String),
-    Statement(Vec<Token>),
+    Var(String),
+    Flag(String, Option<String>),
+    Statement(String),
+    Range(Vec<String>),
+    Pattern(Vec<Token>),
     Bind,
     Glob,
     Pipe,
-    Unknown,
+    Seq,
+    LParen,
+    RParen,
     EOF
 }
 
 impl Token {
     pub fn is_atomic(&self) -> bool {
         match self {
-            Token::ID(_) | Token::Str(_) | Token::Num(_) | Token::Command(_) | Token::Statement(_) => true,
+            Token::ID(_) | Token::Str(_) | Token::Num(_) | Token::Command(_) | Token::Statement(_) | Token::Pattern(_) | Token::Var(_) => true,
             _ => false
         }
     }
 
+    /// How the token is named in error messages.
+    fn describe(&self) -> String {
+        match self {
+            Token::Pipe => String::from("`>`"),
+            Token::Bind => String::from("`&`"),
+            Token::Seq => String::from("`;`"),
+            Token::LParen => String::from("`(`"),
+            Token::RParen => String::from("`)`"),
+            Token::Glob => String::from("`*`"),
+            Token::EOF => String::from("the end of input"),
+            Token::Command(name) => format!("`:{}`", name),
+            Token::Var(name) => format!("`${}`", name),
+            Token::Flag(name, _) => format!("`--{}`", name),
+            Token::Str(value) => format!("\"{}\"", value),
+            Token::Num(num) => format!("`{}`", num),
+            token => format!("`{}`", token.get_string_value())
+        }
+    }
+
     pub fn get_string_value(&self) -> String {
         match self {
-            Token::ID(value) | Token::Str(value) | Token::Command(value) => String::from(value),
+            Token::ID(value) | Token::Str(value) | Token::Command(value) | Token::Statement(value) => String::from(value),
+            Token::Extension(ext) => format!(".{}", ext.get_string_value()),
+            Token::Glob => String::from("*"),
+            Token::Pattern(parts) => parts.iter().map(|part| part.get_string_value()).collect(),
             _ => {String::new()}
         }
     }
@@ -48,26 +216,43 @@ impl Token {
 
 struct Lexer {
     src:String,
+    chars:Vec<(usize, char)>,
     index:usize,
-    tokens:Box<Vec<Token>>
+    tokens:Vec<(Token, Span)>
 }
 
 impl Lexer {
     
     pub fn new(src:String) -> Lexer {
         Lexer {
+            chars: src.char_indices().collect(),
             src,
             index: 0,
-            tokens: Box::new(Vec::new())
+            tokens: Vec::new()
         }
     }
 
     fn curr_char(&self) -> char {        
-        match self.src.chars().nth(self.index) {
-            Some(c) => c,
+        match self.chars.get(self.index) {
+            Some((_, c)) => *c,
+            None => '\0'
+        }
+
+    }
+
+    fn peek(&self) -> char {
+        match self.chars.get(self.index + 1) {
+            Some((_, c)) => *c,
             None => '\0'
         }
+    }
 
+    /// Byte offset of the char at `index`, or the end of the source past the last char.
+    fn byte_at(&self, index:usize) -> usize {
+        match self.chars.get(index) {
+            Some((byte, _)) => *byte,
+            None => self.src.len()
+        }
     }
     
     fn next(&mut self) {
@@ -75,11 +260,11 @@ impl Lexer {
     }
     
     fn back(&mut self) {
-        self.index -= 1;
+        self.index = self.index.saturating_sub(1);
     }
 
     fn skip_whitespace(&mut self) {
-        while self.curr_char() == ' ' || self.curr_char() == '\t' || self.curr_char() == '\n' {
+        while self.curr_char() == ' ' || self.curr_char() == '\t' || self.curr_char() == '\r' {
             self.next();
         }
     }
@@ -87,11 +272,11 @@ impl Lexer {
     fn parse_fn(&mut self, func:fn(char) -> bool) -> String {
         let start_index = self.index;
 
-        while func(self.curr_char()) {
+        while self.curr_char() != '\0' && func(self.curr_char()) {
             self.next();
         }
         
-        let res = &self.src.clone()[start_index as usize ..self.index as usize];
+        let res = &self.src[self.byte_at(start_index)..self.byte_at(self.index)];
         return String::from(res);
 
     }
@@ -119,11 +304,11 @@ impl Lexer {
         return String::from(res);
     }
 */
-    fn parse_num(&mut self) -> f32 {
+    fn parse_num(&mut self) -> Option<f32> {
         let start_index = self.index;
         let mut is_float = false;
 
-        while (self.curr_char().is_digit(10) || (self.curr_char() == '.' && !is_float))&& self.curr_char() != '\0'{
+        while (self.curr_char().is_ascii_digit() || (self.curr_char() == '.' && !is_float))&& self.curr_char() != '\0'{
 
             if self.curr_char() == '.' {
                 is_float = true;
@@ -132,216 +317,421 @@ impl Lexer {
             self.next();
         }
         
-        let res = &self.src.clone()[start_index as usize ..self.index as usize];
+        let res = self.src[self.byte_at(start_index)..self.byte_at(self.index)].parse::<f32>().ok();
         self.back();
-        return res.parse::<f32>().expect("not a float"); 
+        return res; 
+    }
+    fn is_word_char(c:char) -> bool {
+        (c.is_alphabetic() || c == '_'  || c.is_ascii_digit()) && c != '\0'
     }
-    fn lex(&mut self) -> Token {
+
+    /// Parses the inside of a `[...]` index list such as `1-4`, `1,3,7` or `01-12`.
+    /// Bounds written with a leading zero pad every index to the same width.
+    fn parse_range(spec:&str) -> Option<Vec<String>> {
+        let mut indexes = Vec::new();
+
+        for part in spec.split(',') {
+            let part = part.trim();
+            let (lo, hi) = match part.split_once('-') {
+                Some((lo, hi)) => (lo.trim(), hi.trim()),
+                None => (part, part)
+            };
+
+            if lo.is_empty() || hi.is_empty() || !lo.chars().chain(hi.chars()).all(|c| c.is_ascii_digit()) {
+                return None;
+            }
+
+            let width = if lo.len() > 1 && lo.starts_with('0') {lo.len()} else {0};
+            let (lo, hi) = (lo.parse::<u64>().ok()?, hi.parse::<u64>().ok()?);
+            if lo > hi || hi - lo > 100_000 {
+                return None;
+            }
+
+            for i in lo..=hi {
+                indexes.push(format!("{:0width$}", i, width = width));
+            }
+        }
+
+        Some(indexes)
+    }
+
+    fn is_path_char(c:char) -> bool {
+        Lexer::is_word_char(c) || c == '/' || c == '~' || c == '-'
+    }
+
+    /// Lexes a path that may contain `*` globs, `[...]` index lists and `.ext` suffixes.
+    /// Plain names stay a single `ID`, anything else becomes a `Pattern` of parts.
+    fn parse_word(&mut self) -> Result<Token, FilyError> {
+        let mut parts:Vec<Token> = Vec::new();
+
+        loop {
+            match self.curr_char() {
+                '[' => {
+                    let start = self.byte_at(self.index);
+                    self.next();
+                    let spec = self.parse_fn(|c| c != ']');
+                    if self.curr_char() != ']' {
+                        return Err(FilyError::at("unclosed `[`", (start, start + 1)));
+                    }
+                    self.next();
+
+                    match Lexer::parse_range(&spec) {
+                        Some(indexes) => parts.push(Token::Range(indexes)),
+                        None => return Err(FilyError::at(
+                            format!("invalid index list `[{}]`, expected something like `[1-4]` or `[1,3,7]`", spec),
+                            (start, self.byte_at(self.index))
+                        )),
+                    }
+                }
+                '*' => {
+                    self.next();
+                    parts.push(Token::Glob);
+                }
+                '.' if Lexer::is_word_char(self.peek()) => {
+                    self.next();
+                    let ext = self.parse_fn(Lexer::is_word_char);
+                    parts.push(Token::Extension(Box::new(Token::ID(ext))));
+                }
+                '.' => {
+                    self.next();
+                    match parts.last_mut() {
+                        Some(Token::ID(text)) => text.push('.'),
+                        _ => parts.push(Token::ID(String::from(".")))
+                    }
+                }
+                c if Lexer::is_path_char(c) => {
+                    let text = self.parse_fn(Lexer::is_path_char);
+                    match parts.last_mut() {
+                        Some(Token::ID(prev)) => prev.push_str(&text),
+                        _ => parts.push(Token::ID(text))
+                    }
+                }
+                _ => break
+            }
+        }
+        self.back();
+
+        if parts.len() == 1 {
+            if let Token::ID(text) = &parts[0] {
+                if text.chars().all(Lexer::is_word_char) {
+                    return Ok(parts.remove(0));
+                }
+            }
+        }
+        Ok(Token::Pattern(parts))
+    }
+
+    /// Whether the word starting here is a plain number like `2` or `0.5`, as opposed
+    /// to a name that merely starts with digits such as `2024-notes.txt`.
+    fn is_number_ahead(&self) -> bool {
+        let rest:Vec<char> = self.chars[self.index..].iter().map(|(_, c)| *c).collect();
+        let mut i = 0;
+        let mut seen_dot = false;
+
+        while i < rest.len() && (rest[i].is_ascii_digit() || (rest[i] == '.' && !seen_dot)) {
+            seen_dot |= rest[i] == '.';
+            i += 1;
+        }
+
+        let end = rest.get(i).copied().unwrap_or('\0');
+        i > 0 && rest[i - 1] != '.' && !Lexer::is_path_char(end) && end != '[' && end != '*' && end != '.'
+    }
+
+    fn lex(&mut self) -> Result<Token, FilyError> {
         self.skip_whitespace();
+        let start = self.byte_at(self.index);
         match self.curr_char() {
             '>' => {
-                return Token::Pipe;
+                return Ok(Token::Pipe);
             },
-            '*' => {
-                return Token::Glob;
-            }
-            '.' => {
-                self.next();
-                return Token::Extension(Box::new(self.lex()));
+            '*' | '.' | '/' | '~' => {
+                return self.parse_word();
             }
             ':' => {
                 self.next();
-                   let res = self.parse_fn(|c| {
-                       (c.is_alphabetic() || c == '_'  || c.is_digit(10)) && c != '\0'
-                   });
+                   let res = self.parse_fn(Lexer::is_word_char);
                 self.back();
 
-                return Token::Command(res);
+                if res.is_empty() {
+                    return Err(FilyError::at("expected a command name after `:`", (start, start + 1)));
+                }
+                return Ok(Token::Command(res));
             }
-            ' ' | '\t' | '\n' => {
-                self.skip_whitespace();
-                return self.lex();
+            '$' => {
+                self.next();
+                let res = self.parse_fn(Lexer::is_word_char);
+                self.back();
+
+                if res.is_empty() {
+                    return Err(FilyError::at("expected a variable name after `$`", (start, start + 1)));
+                }
+                return Ok(Token::Var(res));
             }
             '"' => {
                 self.next();
-                return Token::Str(self.parse_fn(|c| {
-                    c != '"' && c != '\0'
-                }));
-                //return Token::Str(self.parse_string());
+                let res = self.parse_fn(|c| c != '"');
+                if self.curr_char() != '"' {
+                    return Err(FilyError::at("unterminated string", (start, self.src.len())));
+                }
+                return Ok(Token::Str(res));
             }
             '|' => {
                 self.next();
-                let toks = Lexer::run(self.parse_fn(|c| {
-                    c != '|' && c != '\0'
-                }));
-                return Token::Statement(toks);
+                let body = self.parse_fn(|c| c != '|');
+                if self.curr_char() != '|' {
+                    return Err(FilyError::at("unterminated `|statement|`", (start, self.src.len())));
+                }
+
+                let toks = Lexer::run(body.clone()).map_err(|e| e.shifted(start + 1))?;
+                Parser::run(toks.into()).map_err(|e| e.shifted(start + 1))?;
+                return Ok(Token::Statement(body));
+            }
+            '-' if self.peek() == '-' => {
+                self.next();
+                self.next();
+                let name = self.parse_fn(|c| Lexer::is_word_char(c) || c == '-');
+                if name.is_empty() {
+                    return Err(FilyError::at("expected a flag name after `--`", (start, start + 2)));
+                }
+
+                if self.curr_char() != '=' {
+                    self.back();
+                    return Ok(Token::Flag(name, None));
+                }
+                self.next();
+                let value = self.parse_fn(|c| !c.is_whitespace() && !"><&;()|".contains(c));
+                self.back();
+                return Ok(Token::Flag(name, Some(value)));
             }
             '&' => {
-                return Token::Bind;
+                return Ok(Token::Bind);
+            }
+            // a line break ends a statement just like `;`
+            ';' | '\n' => {
+                return Ok(Token::Seq);
+            }
+            '(' => {
+                return Ok(Token::LParen);
             }
-            '.' => {
-                return Token::Num(self.parse_num());
+            ')' => {
+                return Ok(Token::RParen);
             }
             ch => {
                 //parse ID
-               if ch.is_alphabetic() || ch.is_digit(10) || ch == '_' {
-                   let res = Token::ID(self.parse_fn(|c| {
-                       (c.is_alphabetic() || c == '_'  || c.is_digit(10)) && c != '\0'
-                   }));
-                   self.back();
-                   return res;
-               } 
-
-               if ch.is_digit(10) {
-                    return Token::Num(self.parse_num());
+               if ch.is_ascii_digit() && self.is_number_ahead() {
+                   return match self.parse_num() {
+                       Some(num) => Ok(Token::Num(num)),
+                       None => Err(FilyError::at("invalid number", (start, self.byte_at(self.index + 1))))
+                   };
                }
 
-               return Token::Unknown;
+               if ch.is_alphabetic() || ch.is_ascii_digit() || ch == '_' || ch == '[' {
+                   return self.parse_word();
+               } 
+
+               return Err(FilyError::at(format!("unexpected character `{}`", ch), (start, start + ch.len_utf8())));
             }
         }
 
     }
     
-    pub fn run(src:String) -> Vec<Token> {
+    pub fn run(src:String) -> Result<Vec<(Token, Span)>, FilyError> {
         let mut lexer = Lexer::new(src);
         
-        while lexer.index < lexer.src.len() && lexer.curr_char() != '\0' {
-            let tok = lexer.lex();
-            if tok != Token::Unknown{
-                lexer.tokens.push(tok);
+        loop {
+            lexer.skip_whitespace();
+            if lexer.curr_char() == '\0' {
+                break;
             }
+
+            let start = lexer.byte_at(lexer.index);
+            let tok = lexer.lex()?;
             lexer.next();
+            lexer.tokens.push((tok, (start, lexer.byte_at(lexer.index))));
         }
-        lexer.tokens.push(Token::EOF);
-        *lexer.tokens
+        let end = lexer.src.len();
+        lexer.tokens.push((Token::EOF, (end, end)));
+        Ok(lexer.tokens)
     }
 
 }
 
-type NodeBranch<T> = Option<Box<Node<T>>>;
-
+/// A parsed fily expression. Precedence from loosest to tightest is sequencing
+/// (`;` or juxtaposition), binding (`&`) and piping (`>`); parentheses group.
 #[derive(Debug, PartialEq, Clone)]
-struct Node<T> where T: Clone{
-    value: T,
-    left: NodeBranch<T>,
-    right: NodeBranch<T> 
+enum Expr {
+    Atom(Token, Span),
+    Call(Token, Vec<(Token, Span)>, Span),
+    Pipe(Box<Expr>, Box<Expr>),
+    Bind(Box<Expr>, Box<Expr>),
+    Group(Vec<Expr>, Span),
 }
 
-impl<T: std::clone::Clone> Node<T> {
-    pub fn new(value:T) -> Node<T> {
-        Node {
-            value,
-            left: None,
-            right: None
-        }
-    }
-
-    pub fn new_with_branches(value:T, left:NodeBranch<T>, right:NodeBranch<T>) -> Node<T>{
-        Node {
-            value,
-            left,
-            right
+impl Expr {
+    fn span(&self) -> Span {
+        match self {
+            Expr::Atom(_, span) | Expr::Call(_, _, span) | Expr::Group(_, span) => *span,
+            Expr::Pipe(left, right) | Expr::Bind(left, right) => (left.span().0, right.span().1)
         }
     }
-
-    pub fn package(self) -> Option<Box<Self>> {
-        return Some(Box::new(self.clone()));
-    }
-}
-
-trait UnwrapNode {
-    fn unwrap(&self) -> Token;
-}
-
-impl UnwrapNode for Option<Box<Node<Token>>> {
-    fn unwrap(&self) -> Token {
-        return match self {
-             Some(n) => n.value.clone(),
-             None => Token::Unknown
-        };
-
-    }
 }
 
+/// How deep `(`, `>` and `&` may nest, so parsing and evaluating stay well within
+/// the stack.
+const MAX_DEPTH:usize = 256;
 
 struct Parser {
-    tokens: Arc<[Token]>,
+    tokens: Arc<[(Token, Span)]>,
     index:usize,
-    nodes: Vec<Node<Token>>,
-    curr_token: Token
+    curr_token: Token,
+    curr_span: Span,
+    depth:usize
 } 
 
 impl Parser {
-    pub fn new(tokens: Arc<[Token]>) -> Parser {
-        let tok = if tokens.len() > 0 { tokens[0].clone() } else { Token::Unknown };
+    pub fn new(tokens: Arc<[(Token, Span)]>) -> Parser {
+        let (tok, span) = if tokens.len() > 0 { tokens[0].clone() } else { (Token::EOF, (0, 0)) };
 
         Parser {
-            tokens: tokens.clone().into(),
+            tokens,
             index: 0,
-            nodes: Vec::new(),
             curr_token: tok,
+            curr_span: span,
+            depth: 0
+        }
+    }
+
+    fn nest(&mut self, span:Span) -> Result<(), FilyError> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(FilyError::at(format!("expression is too deep, at most {} nested `(`, `>` and `&` are allowed", MAX_DEPTH), span));
         }
+        Ok(())
     }
 
     fn step(&mut self) {
         self.index += 1;
-        self.curr_token = if self.index < self.tokens.len() {
-            self.tokens[self.index].clone()
+        if self.index < self.tokens.len() {
+            (self.curr_token, self.curr_span) = self.tokens[self.index].clone();
         } else {
-            Token::EOF
-        };
+            self.curr_token = Token::EOF;
+            self.curr_span = (self.curr_span.1, self.curr_span.1);
+        }
     }
 
-    fn factor(&mut self) -> Node<Token> {
-        let token = self.curr_token.clone(); // Clone instead of borrowing
+    fn term(&mut self) -> Result<Expr, FilyError> {
+        let token = self.curr_token.clone();
+        let span = self.curr_span;
         match token {
-            Token::Str(_) | Token::Command(_) | Token::ID(_) | Token::Num(_) | Token::Statement(_) | Token::Glob => {
+            Token::LParen => {
+                self.nest(span)?;
+                self.step();
+                let body = self.sequence()?;
+                if self.curr_token != Token::RParen {
+                    return Err(FilyError::at("unclosed `(`", span));
+                }
+                let end = self.curr_span.1;
                 self.step();
-                Node::new(token)
+                self.depth -= 1;
+                Ok(Expr::Group(body, (span.0, end)))
             }
-            _ => {
+            Token::Command(_) => {
                 self.step();
-                Node::new(Token::EOF)
+                let args = self.args(true);
+                let end = args.last().map(|(_, s)| s.1).unwrap_or(span.1);
+                Ok(Expr::Call(token, args, (span.0, end)))
             }
+            Token::ID(_) if matches!(self.curr_token_after(1), Token::Flag(..)) => {
+                self.step();
+                let args = self.args(false);
+                let end = args.last().map(|(_, s)| s.1).unwrap_or(span.1);
+                Ok(Expr::Call(token, args, (span.0, end)))
+            }
+            Token::Str(_) | Token::ID(_) | Token::Num(_) | Token::Statement(_) | Token::Pattern(_) | Token::Var(_) => {
+                self.step();
+                Ok(Expr::Atom(token, span))
+            }
+            token => Err(FilyError::at(format!("expected a name, value or command but found {}", token.describe()), span))
         }
     }
 
-    fn term(&mut self) -> Node<Token> {
-        let factor = self.factor(); // Get a factor node
-        match self.curr_token {
-            Token::Extension(_) => {
-                Node::new_with_branches(self.curr_token.clone(), factor.package(), None)
-            }
-            _ => {
-                factor
+    fn curr_token_after(&self, offset:usize) -> Token {
+        match self.tokens.get(self.index + offset) {
+            Some((token, _)) => token.clone(),
+            None => Token::EOF
+        }
+    }
+
+    /// Collects the arguments following a command. Stages only take flags, since a
+    /// bare word after them starts the next statement.
+    fn args(&mut self, positional:bool) -> Vec<(Token, Span)> {
+        let mut args = Vec::new();
+        loop {
+            match &self.curr_token {
+                Token::Flag(..) => {},
+                Token::Str(_) | Token::ID(_) | Token::Num(_) | Token::Pattern(_) | Token::Var(_) | Token::Statement(_) if positional => {},
+                _ => return args
             }
+            args.push((self.curr_token.clone(), self.curr_span));
+            self.step();
         }
+    }
 
+    /// Steps past an operator, letting the expression continue on the next line.
+    fn step_operator(&mut self) {
+        self.step();
+        while self.curr_token == Token::Seq {
+            self.step();
+        }
     }
 
-    fn expr(&mut self) -> Node<Token> {
-        let term = self.term(); // Get a term node
-        match self.curr_token {
-            Token::Pipe | Token::Bind => {
-                let tok = self.curr_token.clone();
-                self.step();
-                Node::new_with_branches(tok, term.package(), self.expr().package()) 
-            },
-            _ => term, // Return term as it is
+    /// Every stage nests the pipeline before it one level deeper.
+    fn pipe(&mut self) -> Result<Expr, FilyError> {
+        let depth = self.depth;
+        let mut expr = self.term()?;
+        while self.curr_token == Token::Pipe {
+            self.nest(self.curr_span)?;
+            self.step_operator();
+            expr = Expr::Pipe(Box::new(expr), Box::new(self.term()?));
         }
+        self.depth = depth;
+        Ok(expr)
     }
 
-    pub fn run(tokens: Arc<[Token]>) -> Vec<Node<Token>>{
-        let mut parser = Parser::new(tokens.clone());
-        if tokens.len() <= 0 {
-            return vec![];
+    fn bind(&mut self) -> Result<Expr, FilyError> {
+        let expr = self.pipe()?;
+        if self.curr_token == Token::Bind {
+            self.nest(self.curr_span)?;
+            self.step_operator();
+            let body = self.bind()?;
+            self.depth -= 1;
+            return Ok(Expr::Bind(Box::new(expr), Box::new(body)));
         }
-        while parser.curr_token != Token::EOF {
-            let res = parser.expr();
+        Ok(expr)
+    }
 
-            parser.nodes.push(res);
+    /// Parses statements until a closing paren or the end of input.
+    fn sequence(&mut self) -> Result<Vec<Expr>, FilyError> {
+        let mut statements = Vec::new();
+        loop {
+            while self.curr_token == Token::Seq {
+                self.step();
+            }
+            if self.curr_token == Token::EOF || self.curr_token == Token::RParen {
+                return Ok(statements);
+            }
+            statements.push(self.bind()?);
         }
-        parser.nodes.clone()
+    }
+
+    pub fn run(tokens: Arc<[(Token, Span)]>) -> Result<Vec<Expr>, FilyError> {
+        let mut parser = Parser::new(tokens);
+        let statements = parser.sequence()?;
+
+        if parser.curr_token == Token::RParen {
+            return Err(FilyError::at("unmatched `)`", parser.curr_span));
+        }
+        Ok(statements)
     }
 }
 /*
@@ -369,48 +759,825 @@ fn eval_into_commands(tokens:Vec<Token>) -> Vec<Command> {
 }
 */
 
-fn name_to_command(name:String, piped_value:String) -> Command {
-    match &name as &str {
+/// The kinds of value that flow between pipeline stages. Stages declare which kinds
+/// they accept so a mismatch can be reported instead of silently producing nothing.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ValueKind {
+    None,
+    Str,
+    Num,
+    Paths,
+    Lines,
+    Table,
+}
+
+impl ValueKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ValueKind::None => "nothing",
+            ValueKind::Str => "text",
+            ValueKind::Num => "number",
+            ValueKind::Paths => "paths",
+            ValueKind::Lines => "lines",
+            ValueKind::Table => "table",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Value {
+    None,
+    Str(String),
+    Num(f32),
+    Paths(Vec<PathBuf>),
+    Lines(Vec<String>),
+    Table(Vec<Vec<String>>),
+}
+
+impl Value {
+    pub fn kind(&self) -> ValueKind {
+        match self {
+            Value::None => ValueKind::None,
+            Value::Str(_) => ValueKind::Str,
+            Value::Num(_) => ValueKind::Num,
+            Value::Paths(_) => ValueKind::Paths,
+            Value::Lines(_) => ValueKind::Lines,
+            Value::Table(_) => ValueKind::Table,
+        }
+    }
+
+    /// Renders the value as text, one path or line per row and tables with padded columns.
+    pub fn to_text(&self) -> String {
+        match self {
+            Value::None => String::new(),
+            Value::Str(s) => s.clone(),
+            Value::Num(n) => n.to_string(),
+            Value::Paths(paths) => paths.iter()
+                .map(|p| p.to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+                .join("\n"),
+            Value::Lines(lines) => lines.join("\n"),
+            Value::Table(rows) => {
+                let columns = rows.iter().map(|r| r.len()).max().unwrap_or(0);
+                let widths:Vec<usize> = (0..columns)
+                    .map(|c| rows.iter().filter_map(|r| r.get(c)).map(|cell| cell.chars().count()).max().unwrap_or(0))
+                    .collect();
+
+                rows.iter()
+                    .map(|row| row.iter().enumerate()
+                        .map(|(c, cell)| format!("{:<width$}", cell, width = widths[c]))
+                        .collect::<Vec<_>>()
+                        .join("  ")
+                        .trim_end()
+                        .to_string())
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            }
+        }
+    }
+
+    /// A one line description used when listing variables.
+    pub fn summary(&self) -> String {
+        match self {
+            Value::Paths(paths) => format!("{} paths", paths.len()),
+            Value::Lines(lines) => format!("{} lines", lines.len()),
+            Value::Table(rows) => format!("table of {} rows", rows.len()),
+            value => format!("{:?}", value.to_text()),
+        }
+    }
+}
+
+/// Variables bound with `> $name`. The environment outlives a single prompt, so
+/// values assigned in one submission can be read by the next.
+#[derive(Debug, Default)]
+pub struct Environment {
+    vars: BTreeMap<String, Value>,
+    filter: Filter
+}
+
+impl Environment {
+    pub fn new() -> Environment {
+        Environment { vars: BTreeMap::new(), filter: Filter::default() }
+    }
+
+    pub fn get(&self, name:&str) -> Option<&Value> {
+        self.vars.get(name)
+    }
+
+    pub fn set(&mut self, name:String, value:Value) {
+        self.vars.insert(name, value);
+    }
+
+    /// What globs leave out, set to the filter of the window a prompt came from.
+    pub fn set_filter(&mut self, filter:Filter) {
+        self.filter = filter;
+    }
+
+    pub fn vars(&self) -> impl Iterator<Item = (&String, &Value)> {
+        self.vars.iter()
+    }
+}
+
+/// The arguments a command or stage was called with, e.g. `:win title "text"` or
+/// `head --lines=5`. Each argument keeps its span for error reporting.
+#[derive(Debug, Default)]
+pub struct Args {
+    positional: Vec<(Value, Span)>,
+    raw: Vec<String>,
+    flags: Vec<(String, Option<String>, Span)>,
+    span: Span,
+}
+
+impl Args {
+    pub fn get(&self, index:usize) -> Option<&Value> {
+        self.positional.get(index).map(|(value, _)| value)
+    }
+
+    /// The argument as it was written, before names were resolved to paths.
+    pub fn raw(&self, index:usize) -> Option<&str> {
+        self.raw.get(index).map(String::as_str)
+    }
+
+    /// The argument as text, with names kept as written instead of resolved to paths.
+    pub fn text(&self, index:usize) -> Option<String> {
+        match (self.get(index)?, self.raw(index)) {
+            (Value::Paths(_), Some(raw)) if !raw.is_empty() => Some(String::from(raw)),
+            (value, _) => Some(value.to_text())
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.positional.len()
+    }
+
+    pub fn has_flag(&self, name:&str) -> bool {
+        self.flags.iter().any(|(flag, _, _)| flag == name)
+    }
+
+    pub fn flag_value(&self, name:&str) -> Option<&str> {
+        self.flags.iter()
+            .find(|(flag, _, _)| flag == name)
+            .and_then(|(_, value, _)| value.as_deref())
+    }
+
+    /// A numeric flag such as `--lines=5`, reporting values that are not whole numbers.
+    pub fn flag_num(&self, name:&str) -> Result<Option<usize>, FilyError> {
+        match self.flags.iter().find(|(flag, _, _)| flag == name) {
+            Some((_, Some(value), span)) => value.parse::<usize>()
+                .map(Some)
+                .map_err(|_| FilyError::at(format!("`--{}` expects a whole number", name), *span)),
+            Some((_, None, span)) => Err(FilyError::at(format!("`--{}` expects a value, e.g. `--{}=5`", name, name), *span)),
+            None => Ok(None)
+        }
+    }
+
+    pub fn flag_error(&self, name:&str, message:impl Into<String>) -> FilyError {
+        let span = self.flags.iter().find(|(flag, _, _)| flag == name).map(|(_, _, span)| *span).unwrap_or(self.span);
+        FilyError::at(message, span)
+    }
+
+    pub fn error(&self, index:usize, message:impl Into<String>) -> FilyError {
+        let span = self.positional.get(index).map(|(_, span)| *span).unwrap_or(self.span);
+        FilyError::at(message, span)
+    }
+
+    /// Rejects positional arguments past `max` and flags not listed in `flags`.
+    pub fn check(&self, name:&str, max:usize, flags:&[&str]) -> Result<(), FilyError> {
+        if let Some((_, span)) = self.positional.get(max) {
+            return Err(FilyError::at(format!("too many arguments for `{}`", name), *span));
+        }
+        match self.flags.iter().find(|(flag, _, _)| !flags.contains(&flag.as_str())) {
+            Some((flag, _, span)) => Err(FilyError::at(format!("`{}` has no flag `--{}`", name, flag), *span)),
+            None => Ok(())
+        }
+    }
+}
+
+fn name_to_command(name:String, piped_value:Value, args:&Args, dir:&Path) -> Result<Command, FilyError> {
+    let command = match &name as &str {
         "win" => {
-            if piped_value != String::new(){
-                return Command::Win(piped_value);
+            args.check(":win", 2, &[])?;
+            let title = args.text(0);
+            let contents = match (args.get(1), piped_value) {
+                (Some(text), _) => text.to_text(),
+                (None, Value::None) if title.is_none() => String::from(&name),
+                (None, piped_value) => piped_value.to_text()
+            };
+            Command::Win(contents, title)
+        },
+        "q" => {
+            args.check(":q", 1, &[])?;
+            match args.get(0) {
+                Some(Value::Num(n)) if *n >= 1.0 && n.fract() == 0.0 => Command::Quit(Some(*n as usize)),
+                Some(_) => return Err(args.error(0, "`:q` expects a window number, e.g. `:q 2`")),
+                None => Command::Quit(None)
+            }
+        },
+        "e" => {
+            args.check(":e", 1, &[])?;
+            match args.get(0) {
+                Some(Value::Paths(paths)) if paths.len() == 1 && paths[0].is_dir() => Command::Explorer(Some(paths[0].clone())),
+                Some(_) => return Err(args.error(0, format!("`{}` is not a directory", args.text(0).unwrap_or_default()))),
+                None => Command::Explorer(None)
             }
-            Command::Win(String::from(name))
         },
-        "q" => Command::Quit(),
-        "e" => Command::Explorer(),
         "c" => Command::CopyWin(),
         "lf" => Command::FocusLeft(),
         "rf" => Command::FocusRight(),
+        "vars" => Command::Vars(),
+        "maps" => Command::Maps(),
+        "map" => {
+            args.check(":map", 2, &["in"])?;
+            let (key, body) = match (args.raw(0), args.get(1)) {
+                (Some(key), Some(body)) => (String::from(key), body.to_text()),
+                _ => return Err(FilyError::at("`:map` expects a key and an expression, e.g. `:map gg |:e|`", args.span))
+            };
+            keymap::parse_chord(&key).map_err(|e| args.error(0, e))?;
+            check(body.clone()).map_err(|e| args.error(1, e.message))?;
+
+            let element = args.flag_value("in").map(String::from);
+            if let Some(element) = &element {
+                if !config::ELEMENTS.contains(&element.as_str()) {
+                    return Err(args.flag_error("in", format!("unknown element `{}`, expected one of {}", element, config::ELEMENTS.join(", "))));
+                }
+            }
+            Command::Map(key, body, element)
+        },
+        "cp" | "mv" => {
+            let command = format!(":{}", name);
+            args.check(&command, 1, &["policy"])?;
+            let dest = match path_arg(args, 0, dir) {
+                Some(dest) => dest,
+                None => return Err(FilyError::at(format!("`{}` expects one destination, e.g. `{} ../backup`", command, command), args.span))
+            };
+            let policy = policy_flag(args)?;
+            let op = if name == "cp" {FileOp::Copy(dest, policy)} else {FileOp::Move(dest, policy)};
+            Command::FileOp(op, piped_paths(&command, piped_value, args, dir)?)
+        },
+        "rename" => {
+            args.check(":rename", 2, &["policy", "dry-run"])?;
+            let new_name = match args.raw(0) {
+                Some(new_name) => String::from(new_name),
+                None => return Err(FilyError::at("`:rename` expects a new name, e.g. `:rename notes.md`", args.span))
+            };
+            if args.len() > 1 || Template::is_template(&new_name) {
+                if args.has_flag("policy") {
+                    return Err(args.flag_error("policy", "templates never replace existing entries, clashes are listed before renaming"));
+                }
+                let pattern = args.raw(1).map(template::pattern).transpose().map_err(|e| args.error(1, e))?;
+                let template = Template::parse(&new_name, pattern).map_err(|e| args.error(0, e))?;
+                return Ok(Command::RenameAll(template, piped_paths(":rename", piped_value, args, dir)?, args.has_flag("dry-run")));
+            }
+            if args.has_flag("dry-run") {
+                return Err(args.flag_error("dry-run", "`--dry-run` only applies to templates such as `:rename \"{stem}.jpg\"`"));
+            }
+            if new_name.is_empty() || new_name == "." || new_name == ".." || new_name.contains('/') {
+                return Err(args.error(0, format!("`{}` is not a file name", new_name)));
+            }
+            let paths = piped_paths(":rename", piped_value, args, dir)?;
+            if paths.as_ref().is_some_and(|paths| paths.len() > 1) {
+                return Err(FilyError::at("`:rename` renames a single path, use `:mv` to move several", args.span));
+            }
+            Command::FileOp(FileOp::Rename(new_name, policy_flag(args)?), paths)
+        },
+        "rm" => {
+            args.check(":rm", 0, &["force"])?;
+            let op = if args.has_flag("force") {FileOp::Remove} else {FileOp::Trash};
+            Command::FileOp(op, piped_paths(":rm", piped_value, args, dir)?)
+        },
+        "trash" => Command::Trash(),
+        "undo" => Command::Undo(),
+        "redo" => Command::Redo(),
+        "journal" => Command::Journal(),
+        "yank" | "cut" => Command::Yank(piped_paths(&format!(":{}", name), piped_value, args, dir)?, name == "cut"),
+        "paste" => {
+            args.check(":paste", 0, &["policy"])?;
+            Command::Paste(policy_flag(args)?)
+        },
+        "bulkrename" => Command::BulkRename(piped_paths(":bulkrename", piped_value, args, dir)?),
+        "jobs" => Command::Jobs(),
+        "sort" => {
+            args.check(":sort", 1, &["reverse", "next", "dirs-first", "dirs-mixed"])?;
+            let key = match args.raw(0).map(|name| (name, SortKey::parse(name))) {
+                Some((_, Some(key))) => Some(key),
+                Some((name, None)) => return Err(args.error(0, format!("unknown order `{}`, expected {}", name, sorting::NAMES))),
+                None => None
+            };
+            let flags = ["reverse", "next", "dirs-first", "dirs-mixed"].iter().filter(|flag| args.has_flag(flag)).count();
+            match (key, flags) {
+                (Some(key), 0) => Command::Sort(Sorting::By(key, false)),
+                (Some(key), 1) if args.has_flag("reverse") => Command::Sort(Sorting::By(key, true)),
+                (None, 0) => Command::Sort(Sorting::By(SortKey::Name, false)),
+                (None, 1) if args.has_flag("reverse") => Command::Sort(Sorting::Reverse),
+                (None, 1) if args.has_flag("next") => Command::Sort(Sorting::Next),
+                (None, 1) => Command::Sort(Sorting::DirsFirst(args.has_flag("dirs-first"))),
+                _ => return Err(FilyError::at("`:sort` takes an order, optionally with --reverse, or one of --reverse, --next, --dirs-first or --dirs-mixed", args.span))
+            }
+        },
+        "filter" => {
+            args.check(":filter", 1, &["dotfiles", "ignored"])?;
+            let glob = match args.raw(0).map(|src| (src, Pattern::new(src))) {
+                Some((_, Ok(glob))) => Some(glob),
+                Some((src, Err(e))) => return Err(args.error(0, format!("`{}` is not a valid glob: {}", src, e.msg))),
+                None => None
+            };
+            match (glob, args.has_flag("dotfiles"), args.has_flag("ignored")) {
+                (None, true, false) => Command::Filter(Filtering::Dotfiles),
+                (None, false, true) => Command::Filter(Filtering::Ignored),
+                (glob, false, false) => Command::Filter(Filtering::Glob(glob)),
+                _ => return Err(FilyError::at("`:filter` takes one of --dotfiles, --ignored or a glob", args.span))
+            }
+        },
+        "columns" => {
+            args.check(":columns", usize::MAX, &[])?;
+            // a number after a column is its width
+            let mut chosen:Vec<(Column, u16)> = Vec::new();
+            for i in 0..args.len() {
+                match (args.get(i), args.raw(i).and_then(Column::parse), chosen.len()) {
+                    (Some(Value::Num(n)), _, last) if last > 0 && *n >= 0.0 && n.fract() == 0.0 && *n <= columns::MAX_WIDTH as f32 => chosen[last - 1].1 = *n as u16,
+                    (Some(Value::Num(n)), _, last) if last > 0 && *n > columns::MAX_WIDTH as f32 => return Err(args.error(i, format!("a column is at most {} wide", columns::MAX_WIDTH))),
+                    (Some(Value::Num(_)), _, _) => return Err(args.error(i, "a width is a whole number following a column, e.g. `:columns name size 9`")),
+                    (_, Some(column), _) if chosen.iter().any(|(other, _)| *other == column) => return Err(args.error(i, format!("`{}` is already a column", column.name()))),
+                    (_, Some(column), _) => chosen.push((column, column.default_width())),
+                    _ => return Err(args.error(i, format!("unknown column `{}`, expected {}", args.raw(i).unwrap_or_default(), columns::NAMES)))
+                }
+            }
+            if chosen.is_empty() {
+                chosen = columns::DEFAULT.iter().map(|column| (*column, column.default_width())).collect();
+            }
+            Command::Columns(chosen)
+        },
+        "resize" => {
+            args.check(":resize", 2, &["by"])?;
+            let column = match args.raw(0).map(|name| (name, Column::parse(name))) {
+                Some((_, Some(column))) => column,
+                Some((name, None)) => return Err(args.error(0, format!("unknown column `{}`, expected {}", name, columns::NAMES))),
+                None => return Err(FilyError::at("`:resize` expects a column and a width, e.g. `:resize size 9` or `:resize size --by=-2`", args.span))
+            };
+            let by = match args.flag_value("by").map(str::parse::<i32>) {
+                Some(Ok(by)) => Some(by),
+                Some(Err(_)) => return Err(args.flag_error("by", "`--by` expects a whole number, e.g. `--by=-2`")),
+                None if args.has_flag("by") => return Err(args.flag_error("by", "`--by` expects a whole number, e.g. `--by=-2`")),
+                None => None
+            };
+            let width = match (args.get(1), by) {
+                (Some(Value::Num(n)), None) if *n >= 0.0 && n.fract() == 0.0 && *n <= columns::MAX_WIDTH as f32 => *n as u16,
+                (Some(Value::Num(n)), None) if *n > columns::MAX_WIDTH as f32 => return Err(args.error(1, format!("a column is at most {} wide", columns::MAX_WIDTH))),
+                (None, Some(_)) => 0,
+                (Some(_), Some(_)) => return Err(FilyError::at("`:resize` takes a width or `--by`, not both", args.span)),
+                (Some(_), None) => return Err(args.error(1, "a width is a whole number, 0 fills the room left")),
+                (None, None) => return Err(FilyError::at("`:resize` expects a width, e.g. `:resize size 9` or `:resize size --by=-2`", args.span))
+            };
+            Command::Resize(column, width, by)
+        },
+        "cancel" => {
+            args.check(":cancel", 1, &[])?;
+            match args.get(0) {
+                Some(Value::Num(n)) if *n >= 1.0 && n.fract() == 0.0 => Command::Cancel(Some(*n as usize)),
+                Some(_) => return Err(args.error(0, "`:cancel` expects a job number, e.g. `:cancel 2`")),
+                None => Command::Cancel(None)
+            }
+        },
+        "restore" => Command::Restore(),
+        "purge" => {
+            args.check(":purge", 0, &["all"])?;
+            Command::Purge(args.has_flag("all"))
+        },
+        "mkdir" | "touch" => {
+            let command = format!(":{}", name);
+            args.check(&command, usize::MAX, &[])?;
+            let mut paths = Vec::new();
+            for i in 0..args.len() {
+                match args.get(i) {
+                    Some(Value::Paths(found)) if !found.is_empty() => paths.extend(found.iter().cloned()),
+                    Some(Value::Paths(_)) | Some(Value::Str(_)) => paths.extend(args.raw(i).map(|name| anchor(dir, name))),
+                    _ => return Err(args.error(i, format!("`{}` expects file names", command)))
+                }
+            }
+            if paths.is_empty() {
+                return Err(FilyError::at(format!("`{}` expects a name, e.g. `{} notes`", command, command), args.span));
+            }
+            let op = if name == "mkdir" {FileOp::MakeDir} else {FileOp::Touch};
+            Command::FileOp(op, Some(paths))
+        },
+        "mark" => {
+            args.check(":mark", usize::MAX, &["all", "invert", "clear"])?;
+            let mut paths = match piped_paths(":mark", piped_value, args, dir)? {
+                Some(paths) => paths,
+                None => Vec::new()
+            };
+            for i in 0..args.len() {
+                match args.get(i) {
+                    Some(Value::Paths(found)) => paths.extend(found.iter().cloned()),
+                    _ => return Err(args.error(i, "`:mark` expects paths or a glob, e.g. `:mark *.rs`"))
+                }
+            }
+
+            let modes = ["all", "invert", "clear"].iter().filter(|mode| args.has_flag(mode)).count();
+            if modes > 1 || (modes == 1 && !paths.is_empty()) {
+                return Err(FilyError::at("`:mark` takes one of --all, --invert, --clear or paths", args.span));
+            }
+
+            let marking = if args.has_flag("all") {
+                Marking::All
+            } else if args.has_flag("invert") {
+                Marking::Invert
+            } else if args.has_flag("clear") {
+                Marking::Clear
+            } else if !paths.is_empty() {
+                Marking::Add(paths)
+            } else if args.len() > 0 {
+                return Err(FilyError::at("`:mark` matched nothing", args.span));
+            } else {
+                Marking::Toggle
+            };
+            Command::Mark(marking)
+        },
         "exit" => Command::RequestExit(),
          _ => Command::Unknown 
+    };
+
+    match command {
+        Command::Win(..) | Command::Quit(_) | Command::Explorer(_) | Command::Map(..) | Command::FileOp(..) | Command::Mark(_) | Command::Sort(_) | Command::Filter(_) | Command::Purge(_) | Command::Paste(_) | Command::Cancel(_) | Command::Columns(_) | Command::Resize(..) | Command::Unknown => {},
+        _ => args.check(&format!(":{}", name), 0, &[])?
+    }
+    Ok(command)
+}
+
+/// The paths piped into a file command, or `None` to act on the entry under the cursor.
+fn piped_paths(command:&str, piped_value:Value, args:&Args, dir:&Path) -> Result<Option<Vec<PathBuf>>, FilyError> {
+    match piped_value {
+        Value::None => Ok(None),
+        Value::Paths(paths) if paths.is_empty() => Err(FilyError::at(format!("no paths were piped into `{}`", command), args.span)),
+        Value::Paths(paths) => Ok(Some(paths)),
+        Value::Str(name) => Ok(Some(vec![anchor(dir, &name)])),
+        value => Err(FilyError::at(format!("`{}` expects paths but got {}", command, value.kind().name()), args.span))
+    }
+}
+
+/// What to do when a destination exists, from `--policy=`. Without the flag the user
+/// is asked, or batch mode stops with an error.
+fn policy_flag(args:&Args) -> Result<Policy, FilyError> {
+    if !args.has_flag("policy") {
+        return Ok(Policy::Ask);
+    }
+    match args.flag_value("policy").and_then(Policy::parse) {
+        Some(policy) => Ok(policy),
+        None => Err(args.flag_error("policy", "`--policy` is one of ask, overwrite, skip or rename"))
+    }
+}
+
+/// A single path argument that does not have to exist yet, anchored to `dir`.
+fn path_arg(args:&Args, index:usize, dir:&Path) -> Option<PathBuf> {
+    match args.get(index)? {
+        Value::Paths(paths) if paths.len() == 1 => Some(paths[0].clone()),
+        Value::Paths(paths) if paths.is_empty() => args.raw(index).map(|name| anchor(dir, name)),
+        Value::Str(name) => Some(anchor(dir, name)),
+        _ => None
+    }
+}
+
+/// Anchors a name from a pattern to `dir`, expanding a leading `~` to the home directory.
+fn anchor(dir:&Path, name:&str) -> PathBuf {
+    if name == "~" || name.starts_with("~/") {
+        if let Some(home) = env::var_os("HOME") {
+            let rest = name.trim_start_matches('~').trim_start_matches('/');
+            return if rest.is_empty() {PathBuf::from(home)} else {PathBuf::from(home).join(rest)};
+        }
+    }
+    dir.join(name)
+}
+
+/// Expands a pattern into the paths it names under `dir`. `[...]` index lists turn
+/// into one candidate per index, and missing indexes are skipped silently. When the
+/// pattern holds a `*`, or is a bare `.ext`, each candidate is matched as a glob, and
+/// matches hidden by `filter` are left out.
+fn expand_pattern(parts:&[Token], dir:&Path, filter:&Filter) -> Vec<PathBuf> {
+    let is_glob = parts.contains(&Token::Glob) || matches!(parts, [Token::Extension(_)]);
+    let literal = |text:&str| if is_glob {Pattern::escape(text)} else {String::from(text)};
+    let mut names = vec![String::new()];
+
+    for (i, part) in parts.iter().enumerate() {
+        names = match part {
+            Token::ID(text) => names.into_iter().map(|n| n + &literal(text)).collect(),
+            Token::Extension(ext) if i == 0 && is_glob && parts.len() == 1 => vec![format!("*.{}", literal(&ext.get_string_value()))],
+            Token::Extension(ext) => names.into_iter().map(|n| format!("{}.{}", n, literal(&ext.get_string_value()))).collect(),
+            Token::Glob => names.into_iter().map(|n| n + "*").collect(),
+            Token::Range(indexes) => names.iter()
+                .flat_map(|n| indexes.iter().map(move |i| format!("{}{}", n, i)))
+                .collect(),
+            _ => names
+        };
+    }
+
+    let options = MatchOptions { require_literal_leading_dot: true, ..MatchOptions::new() };
+    let mut paths = Vec::new();
+    for name in names {
+        if !is_glob {
+            let path = anchor(dir, &name);
+            if path.exists() {
+                paths.push(path);
+            }
+            continue;
+        }
+
+        let pattern = match name.strip_prefix('~') {
+            Some(rest) if rest.is_empty() || rest.starts_with('/') => match env::var_os("HOME") {
+                Some(home) => format!("{}{}", Pattern::escape(&home.to_string_lossy()), rest),
+                None => name.clone()
+            },
+            _ => Pattern::escape(&dir.to_string_lossy()) + "/" + &name
+        };
+        let pattern = if name.starts_with('/') {name.clone()} else {pattern};
+
+        if let Ok(matches) = glob_with(&pattern, options) {
+            let mut matches:Vec<PathBuf> = matches.filter_map(|m| m.ok()).collect();
+            filter.retain(&mut matches);
+            paths.extend(matches);
+        }
     }
+    paths
 }
 
-fn eval_into_commands(nodes:Vec<Node<Token>>) -> Vec<Command> {
-    nodes.into_iter().map(|n| {
-        match n.value {
-            Token::Command(name) => name_to_command(name, String::new()),
-            Token::Pipe => {
-                if !n.left.clone().unwrap().value.is_atomic(){
-                    return Command::Unknown;
+/// Turns the optional directory a prompt was submitted from into the directory names
+/// are resolved against. A file resolves to its parent.
+fn resolve_dir(curr_dir:Option<PathBuf>) -> PathBuf {
+    let dir = match curr_dir {
+        Some(dir) => dir,
+        None => env::current_dir().unwrap_or_else(|_| PathBuf::from("./"))
+    };
+
+    if dir.is_file() {
+        return dir.parent().map(|p| p.to_path_buf()).unwrap_or(dir);
+    }
+    dir
+}
+
+struct Evaluator<'a> {
+    dir: PathBuf,
+    env: &'a mut Environment,
+    commands: Vec<Command>
+}
+
+impl<'a> Evaluator<'a> {
+
+    /// The value an atom produces at the start of a pipeline. A bare name that exists
+    /// on disk is a path, anything else is text.
+    fn source(&self, token:Token, span:Span) -> Result<Value, FilyError> {
+        match token {
+            Token::Pattern(parts) => Ok(Value::Paths(expand_pattern(&parts, &self.dir, &self.env.filter))),
+            Token::Var(name) => match self.env.get(&name) {
+                Some(value) => Ok(value.clone()),
+                None => Err(FilyError::at(format!("undefined variable `${}`", name), span))
+            },
+            Token::Num(n) => Ok(Value::Num(n)),
+            Token::ID(name) if self.dir.join(&name).exists() => Ok(Value::Paths(vec![self.dir.join(&name)])),
+            token => Ok(Value::Str(token.get_string_value()))
+        }
+    }
+
+    fn args(&self, tokens:Vec<(Token, Span)>, span:Span) -> Result<Args, FilyError> {
+        let mut args = Args { span, ..Args::default() };
+        for (token, span) in tokens {
+            match token {
+                Token::Flag(name, value) => args.flags.push((name, value, span)),
+                token => {
+                    args.raw.push(token.get_string_value());
+                    args.positional.push((self.source(token, span)?, span));
                 }
+            }
+        }
+        Ok(args)
+    }
 
-                match n.right.unwrap().value {
-                    Token::Command(name) => name_to_command(name, n.left.unwrap().value.get_string_value()),
-                    _ => {Command::Unknown}
+    /// Feeds `input` into a single pipeline stage and returns what flows out of it.
+    fn stage(&mut self, token:Token, span:Span, input:Value, args:Args) -> Result<Value, FilyError> {
+        match token {
+            Token::Command(name) => {
+                match name_to_command(name.clone(), input, &args, &self.dir)? {
+                    Command::Unknown => return Err(FilyError::at(format!("unknown command `{}`", name), span)),
+                    command => self.commands.push(command)
                 }
+                Ok(Value::None)
             },
-            _ => Command::Unknown
+            Token::Var(name) => {
+                self.env.set(name, input.clone());
+                Ok(input)
+            },
+            Token::ID(name) => match builtins::find(&name) {
+                Some(stage) if stage.accepts.contains(&input.kind()) => {
+                    args.check(stage.name, 0, stage.flags)?;
+                    (stage.run)(input, &args, &self.dir)
+                },
+                Some(stage) => {
+                    let mut accepts:Vec<&str> = stage.accepts.iter().map(|k| k.name()).collect();
+                    let last = accepts.pop().unwrap_or("nothing");
+                    let expected = if accepts.is_empty() {String::from(last)} else {format!("{} or {}", accepts.join(", "), last)};
+                    Err(FilyError::at(format!("`{}` expects {} but got {}", stage.name, expected, input.kind().name()), span))
+                },
+                None => Err(FilyError::at(format!("unknown stage `{}`", name), span))
+            },
+            token => Err(FilyError::at(format!("cannot pipe into {}", token.describe()), span))
         }
-    }).collect()
+    }
+
+    /// Evaluates `expr`, feeding it `input` when it sits to the right of a pipe.
+    /// A group hands its input to each of its statements and yields the last value.
+    fn value(&mut self, expr:Expr, input:Option<Value>) -> Result<Value, FilyError> {
+        match expr {
+            Expr::Call(token, args, span) => {
+                let args = self.args(args, span)?;
+                self.stage(token, span, input.unwrap_or(Value::None), args)
+            },
+            Expr::Atom(token, span) => match input {
+                Some(input) => self.stage(token, span, input, Args { span, ..Args::default() }),
+                None => self.source(token, span)
+            },
+            Expr::Pipe(left, right) => {
+                let value = self.value(*left, input)?;
+                self.value(*right, Some(value))
+            },
+            Expr::Group(statements, _) => {
+                let mut value = Value::None;
+                for statement in statements {
+                    value = self.value(statement, input.clone())?;
+                }
+                Ok(value)
+            },
+            Expr::Bind(key, body) => {
+                let span = (key.span().0, body.span().1);
+                match (*key, *body) {
+                    (Expr::Atom(key, key_span), Expr::Atom(Token::Statement(body), _)) if key.is_atomic() => {
+                        let key = key.get_string_value();
+                        keymap::parse_chord(&key).map_err(|e| FilyError::at(e, key_span))?;
+                        self.commands.push(Command::Map(key, body, None));
+                    },
+                    _ => return Err(FilyError::at("`&` binds a key to a |statement|, e.g. `left & |:lf|`", span))
+                }
+                Ok(Value::None)
+            }
+        }
+    }
+}
+
+/// Whether a statement ends by assigning to a variable, in which case batch mode
+/// does not echo its value.
+fn is_assignment(expr:&Expr) -> bool {
+    match expr {
+        Expr::Pipe(_, right) => matches!(**right, Expr::Atom(Token::Var(_), _)),
+        Expr::Group(statements, _) => statements.last().map(is_assignment).unwrap_or(false),
+        _ => false
+    }
+}
+
+/// Evaluates each statement in order. With `echo` set, the value of every statement
+/// that is not an assignment is queued as a `Command::Echo` for batch mode to print.
+fn eval_into_commands(statements:Vec<Expr>, dir:PathBuf, env:&mut Environment, echo:bool) -> Result<Vec<Command>, (Vec<Command>, FilyError)> {
+    let mut evaluator = Evaluator { dir, env, commands: Vec::new() };
+    for statement in statements {
+        let assignment = is_assignment(&statement);
+        match evaluator.value(statement, None) {
+            Ok(Value::None) => {},
+            Ok(value) if echo && !assignment => evaluator.commands.push(Command::Echo(value)),
+            Ok(_) => {},
+            Err(e) => return Err((evaluator.commands, e))
+        }
+    }
+    Ok(evaluator.commands)
+}
+
+fn execute(curr_dir:Option<PathBuf>, src:String, env:&mut Environment, echo:bool) -> Vec<Command> {
+    let statements = Lexer::run(src.clone())
+        .and_then(|tokens| Parser::run(tokens.into()));
+
+    let statements = match statements {
+        Ok(statements) => statements,
+        Err(e) => return vec![Command::Error(e.with_src(&src))]
+    };
+
+    match eval_into_commands(statements, resolve_dir(curr_dir), env, echo) {
+        Ok(commands) => commands,
+        Err((mut commands, e)) => {
+            commands.push(Command::Error(e.with_src(&src)));
+            commands
+        }
+    }
+}
+
+/// Lexes and parses `src` without evaluating it.
+pub fn check(src:String) -> Result<(), FilyError> {
+    Lexer::run(src.clone())
+        .and_then(|tokens| Parser::run(tokens.into()))
+        .map(|_| ())
+        .map_err(|e| e.with_src(&src))
 }
 
-pub fn execute_fily_regex(curr_dir:Option<String>, src:String) -> Vec<Command> {
-    let tokens = Lexer::run(src);
-    let nodes = Parser::run(tokens.clone().into());
-//    println!("{:#?}", tokens);
-//    println!("{:#?}", nodes);
-    eval_into_commands(nodes)
+/// Lexes, parses and evaluates `src`. Commands queued before an error still run,
+/// and the error itself is reported last as a `Command::Error`.
+pub fn execute_fily_regex(curr_dir:Option<PathBuf>, src:String, env:&mut Environment) -> Vec<Command> {
+    execute(curr_dir, src, env, false)
 }
 
+/// Like `execute_fily_regex`, but also echoes the value of each statement so batch
+/// mode can print results that never reach a `:win`.
+pub fn execute_fily_batch(curr_dir:Option<PathBuf>, src:String, env:&mut Environment) -> Vec<Command> {
+    execute(curr_dir, src, env, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn scratch(name:&str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("fily-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn tokens(src:&str) -> Vec<Token> {
+        Lexer::run(String::from(src)).unwrap().into_iter().map(|(token, _)| token).collect()
+    }
+
+    #[test]
+    fn ranges_lex_into_their_indexes() {
+        let digits = |range:&[&str]| Token::Range(range.iter().map(|i| String::from(*i)).collect());
+        assert_eq!(tokens("someFile[1-4].txt"), [
+            Token::Pattern(vec![Token::ID(String::from("someFile")), digits(&["1", "2", "3", "4"]), Token::Extension(Box::new(Token::ID(String::from("txt"))))]),
+            Token::EOF
+        ]);
+        assert_eq!(Lexer::parse_range("1,3,7"), Some(vec![String::from("1"), String::from("3"), String::from("7")]));
+        assert_eq!(Lexer::parse_range("08-10"), Some(vec![String::from("08"), String::from("09"), String::from("10")]));
+        assert_eq!(Lexer::parse_range("4-1"), None);
+        assert_eq!(Lexer::parse_range("a-b"), None);
+    }
+
+    #[test]
+    fn ranges_only_expand_to_existing_names() {
+        let dir = scratch("ranges");
+        for i in 1..=3 {
+            fs::write(dir.join(format!("someFile{}.txt", i)), "").unwrap();
+        }
+        let parts = match tokens("someFile[2-4].txt").remove(0) {
+            Token::Pattern(parts) => parts,
+            token => panic!("expected a pattern, got {:?}", token)
+        };
+        assert_eq!(expand_pattern(&parts, &dir, &Filter::default()), [dir.join("someFile2.txt"), dir.join("someFile3.txt")]);
+    }
+
+    /// Writes a parsed statement with its grouping made explicit.
+    fn shape(expr:&Expr) -> String {
+        match expr {
+            Expr::Atom(token, _) | Expr::Call(token, _, _) => token.get_string_value(),
+            Expr::Pipe(left, right) => format!("({} > {})", shape(left), shape(right)),
+            Expr::Bind(left, right) => format!("({} & {})", shape(left), shape(right)),
+            Expr::Group(statements, _) => format!("[{}]", statements.iter().map(shape).collect::<Vec<_>>().join("; "))
+        }
+    }
+
+    fn parse(src:&str) -> Vec<String> {
+        let tokens = Lexer::run(String::from(src)).unwrap();
+        Parser::run(tokens.into()).unwrap().iter().map(shape).collect()
+    }
+
+    #[test]
+    fn pipes_bind_tighter_than_binds_and_sequences() {
+        assert_eq!(parse("a > b > c"), ["((a > b) > c)"]);
+        assert_eq!(parse("a & b & c"), ["(a & (b & c))"]);
+        assert_eq!(parse("a > b & c > d; e"), ["((a > b) & (c > d))", "e"]);
+        assert_eq!(parse("a b\nc"), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn parentheses_group_statements() {
+        assert_eq!(parse("(a; b) > c"), ["([a; b] > c)"]);
+        assert_eq!(parse("a > (b & c)"), ["(a > [(b & c)])"]);
+        assert_eq!(parse("a >\n b"), ["(a > b)"]);
+    }
+
+    fn error(src:&str) -> (String, Option<Span>) {
+        let e = Lexer::run(String::from(src)).and_then(|tokens| Parser::run(tokens.into())).unwrap_err();
+        (e.message, e.span)
+    }
+
+    #[test]
+    fn syntax_errors_point_at_their_source() {
+        assert_eq!(error("(a"), (String::from("unclosed `(`"), Some((0, 1))));
+        assert_eq!(error("a)"), (String::from("unmatched `)`"), Some((1, 2))));
+        assert_eq!(error("a > )").1, Some((4, 5)));
+        assert_eq!(error("x[3-1]").1, Some((1, 6)));
+        assert_eq!(error(&"(".repeat(300)).1, Some((MAX_DEPTH, MAX_DEPTH + 1)));
+        assert_eq!(error(&"a > ".repeat(300)).1, Some((4 * MAX_DEPTH + 2, 4 * MAX_DEPTH + 3)));
+    }
+
+    #[test]
+    fn evaluation_errors_point_at_the_stage() {
+        let dir = scratch("spans");
+        let commands = execute_fily_regex(Some(dir), String::from("\"a\" > nope"), &mut Environment::new());
+        match &commands[..] {
+            [Command::Error(e)] => assert_eq!((e.message.as_str(), e.span), ("unknown stage `nope`", Some((6, 10)))),
+            commands => panic!("expected one error, got {:?}", commands)
+        }
+    }
+}
@@ -0,0 +1,272 @@
+use std::cell::Cell;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use crate::bulkrename;
+use crate::bulkrename::Step;
+use crate::fileops;
+use crate::fileops::{Change, FileOp, Policy};
+use crate::journal;
+use crate::journal::Entry;
+use crate::trash::TrashItem;
+use crate::trashbin;
+
+/// How often, in entries, a running job reports its progress.
+const REPORT_EVERY:usize = 32;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum JobState {
+    Queued,
+    Running(usize, usize),
+    Finished,
+    Failed(String),
+    Cancelled,
+}
+
+/// A file command handed to the worker thread.
+pub struct Job {
+    pub id: usize,
+    pub name: &'static str,
+    pub paths: usize,
+    pub state: JobState,
+    cancel: Arc<AtomicBool>,
+}
+
+/// What a job does: one file command over its paths, the steps of a bulk rename,
+/// walking a journal entry or purging the trash.
+enum Work {
+    Op(FileOp, Vec<PathBuf>),
+    Renames(Vec<Step>),
+    Undo(Entry),
+    Redo(Entry),
+    Purge(Vec<TrashItem>),
+}
+
+/// What an ended job did, for the journal. Undo and redo jobs hand back the
+/// changes they left and the ones they walked.
+pub enum Outcome {
+    Changed(Vec<Change>),
+    Undone(Entry, Entry),
+    Redone(Entry, Entry),
+}
+
+struct Task {
+    id: usize,
+    work: Work,
+    cancel: Arc<AtomicBool>,
+}
+
+enum Report {
+    Progress(usize, usize, usize),
+    Done(usize, Outcome, Result<(), String>),
+    Conflict(usize, PathBuf, PathBuf),
+}
+
+/// How the user settled a conflict: a policy and whether it applies to the rest of
+/// the job, or `None` to cancel the job.
+pub type Answer = Option<(Policy, bool)>;
+
+/// Runs file commands on a worker thread so the event loop never blocks on the
+/// disk. Jobs run one after another in the order they were issued, so
+/// `:mkdir q; x > :mv q` still sees the directory it just made.
+pub struct Jobs {
+    jobs: Vec<Job>,
+    tasks: Sender<Task>,
+    reports: Receiver<Report>,
+    answers: Sender<Answer>,
+    conflict: Option<(usize, PathBuf, PathBuf)>,
+}
+
+impl Jobs {
+
+    pub fn new() -> Jobs {
+        let (tasks, task_rx) = mpsc::channel();
+        let (report_tx, reports) = mpsc::channel();
+        let (answers, answer_rx) = mpsc::channel();
+        thread::spawn(move || work(task_rx, report_tx, answer_rx));
+        Jobs { jobs: Vec::new(), tasks, reports, answers, conflict: None }
+    }
+
+    pub fn spawn(&mut self, op:FileOp, paths:Vec<PathBuf>) {
+        self.queue(op.name(), paths.len(), Work::Op(op, paths));
+    }
+
+    /// Runs the steps of a `:bulkrename` plan as one job, so it is undone as one.
+    pub fn spawn_renames(&mut self, steps:Vec<Step>) {
+        self.queue(":bulkrename", steps.len(), Work::Renames(steps));
+    }
+
+    pub fn spawn_undo(&mut self, entry:Entry) {
+        self.queue(":undo", entry.len(), Work::Undo(entry));
+    }
+
+    pub fn spawn_redo(&mut self, entry:Entry) {
+        self.queue(":redo", entry.len(), Work::Redo(entry));
+    }
+
+    pub fn spawn_purge(&mut self, items:Vec<TrashItem>) {
+        self.queue(":purge", items.len(), Work::Purge(items));
+    }
+
+    fn queue(&mut self, name:&'static str, paths:usize, work:Work) {
+        let id = self.jobs.len() + 1;
+        let cancel = Arc::new(AtomicBool::new(false));
+        self.jobs.push(Job { id, name, paths, state: JobState::Queued, cancel: cancel.clone() });
+
+        if self.tasks.send(Task { id, work, cancel }).is_err() {
+            self.jobs[id - 1].state = JobState::Failed(String::from("the job runner stopped"));
+        }
+    }
+
+    /// Takes in what the worker reported since the last call and returns the jobs
+    /// that ended, with what they did.
+    pub fn poll(&mut self) -> Vec<(&'static str, Outcome, Result<(), String>)> {
+        let mut ended = Vec::new();
+        while let Ok(report) = self.reports.try_recv() {
+            match report {
+                Report::Progress(id, done, total) => self.jobs[id - 1].state = JobState::Running(done.min(total), total),
+                Report::Done(id, outcome, res) => {
+                    let job = &mut self.jobs[id - 1];
+                    job.state = match &res {
+                        Ok(()) => JobState::Finished,
+                        Err(_) if job.cancel.load(Ordering::Relaxed) => JobState::Cancelled,
+                        Err(e) => JobState::Failed(e.clone())
+                    };
+                    ended.push((job.name, outcome, res));
+                },
+                Report::Conflict(id, from, to) => self.conflict = Some((id, from, to)),
+            }
+        }
+        ended
+    }
+
+    /// Asks job `id`, or every job that has not ended, to stop. A queued job never
+    /// starts and a running one stops at the next entry it touches.
+    pub fn cancel(&mut self, id:Option<usize>) -> Result<(), String> {
+        let mut cancelled = 0;
+        for job in self.jobs.iter().filter(|job| id.is_none_or(|id| job.id == id)) {
+            if matches!(job.state, JobState::Queued | JobState::Running(..)) {
+                job.cancel.store(true, Ordering::Relaxed);
+                cancelled += 1;
+            }
+        }
+        // a job waiting on a conflict would never reach its next entry
+        if self.conflict.as_ref().is_some_and(|(job, ..)| id.is_none_or(|id| *job == id)) {
+            self.answer(None);
+        }
+        match (cancelled, id) {
+            (0, Some(id)) if id == 0 || id > self.jobs.len() => Err(format!("there is no job {}", id)),
+            (0, Some(id)) => Err(format!("job {} has already ended", id)),
+            (0, None) => Err(String::from("there are no running jobs")),
+            _ => Ok(())
+        }
+    }
+
+    /// The destination the worker is waiting on, and the path that would replace it.
+    pub fn conflict(&self) -> Option<(&Path, &Path)> {
+        self.conflict.as_ref().map(|(_, from, to)| (from.as_path(), to.as_path()))
+    }
+
+    pub fn answer(&mut self, answer:Answer) {
+        if let Some((id, ..)) = self.conflict.take() {
+            if answer.is_none() {
+                self.jobs[id - 1].cancel.store(true, Ordering::Relaxed);
+            }
+            let _ = self.answers.send(answer);
+        }
+    }
+
+    pub fn jobs(&self) -> &[Job] {
+        &self.jobs
+    }
+
+    /// The job the worker is on, for the status bar.
+    pub fn running(&self) -> Option<&Job> {
+        self.jobs.iter().find(|job| matches!(job.state, JobState::Running(..)))
+    }
+}
+
+impl Job {
+    pub fn describe(&self) -> String {
+        let state = match &self.state {
+            JobState::Queued => String::from("queued"),
+            JobState::Running(done, total) => format!("running {}/{}", done, total),
+            JobState::Finished => String::from("finished"),
+            JobState::Failed(e) => format!("failed: {}", e),
+            JobState::Cancelled => String::from("cancelled"),
+        };
+        let paths = if self.paths == 1 {String::from("1 path")} else {format!("{} paths", self.paths)};
+        format!("#{} {} {} {}", self.id, self.name, paths, state)
+    }
+}
+
+fn work(tasks:Receiver<Task>, reports:Sender<Report>, answers:Receiver<Answer>) {
+    for task in tasks {
+        if task.cancel.load(Ordering::Relaxed) {
+            let outcome = match task.work {
+                Work::Undo(entry) => Outcome::Undone(entry, Entry::default()),
+                Work::Redo(entry) => Outcome::Redone(entry, Entry::default()),
+                _ => Outcome::Changed(Vec::new())
+            };
+            let _ = reports.send(Report::Done(task.id, outcome, Err(String::from("cancelled"))));
+            continue;
+        }
+
+        let total = match &task.work {
+            Work::Op(op, paths) => paths.iter().map(|path| fileops::count(op, path)).sum(),
+            Work::Renames(steps) => steps.len(),
+            Work::Undo(entry) | Work::Redo(entry) => entry.len(),
+            Work::Purge(items) => items.len()
+        };
+        let _ = reports.send(Report::Progress(task.id, 0, total));
+
+        let done = Cell::new(0);
+        let tick = || {
+            if task.cancel.load(Ordering::Relaxed) {
+                return Err(io::Error::new(io::ErrorKind::Interrupted, "cancelled"));
+            }
+            done.set(done.get() + 1);
+            if done.get() % REPORT_EVERY == 0 {
+                let _ = reports.send(Report::Progress(task.id, done.get(), total));
+            }
+            Ok(())
+        };
+
+        let ask = |from:&Path, to:&Path| {
+            let _ = reports.send(Report::Conflict(task.id, from.to_path_buf(), to.to_path_buf()));
+            match answers.recv() {
+                Ok(Some(answer)) => Ok(answer),
+                _ => Err(io::Error::new(io::ErrorKind::Interrupted, "cancelled"))
+            }
+        };
+
+        let (outcome, res) = match task.work {
+            Work::Op(op, paths) => {
+                let (changes, res) = fileops::apply_with(&op, &paths, &tick, &ask);
+                (Outcome::Changed(changes), res)
+            },
+            Work::Renames(steps) => {
+                let (changes, res) = bulkrename::apply_with(&steps, &tick, &ask);
+                (Outcome::Changed(changes), res)
+            },
+            Work::Undo(mut entry) => {
+                let (reversed, res) = journal::undo_entry(&mut entry, &tick);
+                (Outcome::Undone(entry, reversed), res)
+            },
+            Work::Redo(mut entry) => {
+                let (redone, res) = journal::redo_entry(&mut entry, &tick);
+                (Outcome::Redone(entry, redone), res)
+            },
+            Work::Purge(items) => {
+                let (changes, res) = trashbin::purge_with(&items, &tick);
+                (Outcome::Changed(changes), res)
+            }
+        };
+        if reports.send(Report::Done(task.id, outcome, res)).is_err() {
+            return;
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use crate::appstate::AppState;
+use crate::filyregex::Command;
+use crate::jobs::JobState;
+use crate::prompt::Prompt;
+
+use crossterm::event::KeyEvent;
+
+use ratatui::{
+    layout::{Alignment, Constraint, Direction, Layout, Rect},
+    style::{Color, Style},
+    widgets::{Block, BorderType, Borders, Paragraph},
+    Frame,
+};
+
+/// Lists the background jobs of the session, newest first. The list itself lives
+/// in `AppState`, so the window always shows the current progress.
+#[derive(Clone)]
+pub struct JobsView {
+    prompt:Prompt
+}
+
+impl JobsView {
+
+    pub fn new() -> JobsView {
+        JobsView { prompt: Prompt::default() }
+    }
+
+    pub fn searching(&self) -> bool {
+        self.prompt.is_open()
+    }
+
+    pub fn pulling_info(&self) -> String {
+        self.prompt.text()
+    }
+
+    pub fn handle_input(&mut self, key:KeyEvent) -> Option<Vec<Command>> {
+        if self.prompt.takes(&key) {
+            return self.prompt.handle_input(key);
+        }
+        None
+    }
+
+    pub fn render(&self, f: &mut Frame, appState:&AppState, outter:Rect, isFocused: bool) {
+        f.render_widget(
+        Block::new()
+            .border_type(BorderType::Rounded)
+            .borders(Borders::ALL)
+            .border_style(Style::default().fg(if isFocused {Color::Blue} else {Color::White}))
+            .title(format!("jobs [{}]", appState.jobs().len())),
+        outter);
+
+        let inner = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints(vec![Constraint::Percentage(2), Constraint::Percentage(98)])
+            .split(outter)[1];
+        let rows = (inner.height as usize).saturating_sub(2);
+        let bounds = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(vec![Constraint::Length(1); rows + 1])
+            .split(inner);
+
+        for (row, job) in appState.jobs().iter().rev().take(rows).enumerate() {
+            let color = match job.state {
+                JobState::Queued | JobState::Running(..) => Color::Yellow,
+                JobState::Finished => Color::Green,
+                JobState::Failed(_) => Color::Red,
+                JobState::Cancelled => Color::White,
+            };
+            let p = Paragraph::new(job.describe())
+                .style(Style::default().fg(color))
+                .alignment(Alignment::Center);
+            f.render_widget(p, bounds[row + 1]);
+        }
+    }
+
+}
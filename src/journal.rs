@@ -0,0 +1,184 @@
+use std::fs;
+use std::io;
+use chrono::Local;
+use crate::fileops;
+use crate::fileops::{Change, Tick};
+use crate::trash;
+
+/// The changes one file command made, undone and redone as a unit.
+#[derive(Default)]
+pub struct Entry {
+    command: &'static str,
+    changes: Vec<Change>,
+}
+
+/// Every change fily made on disk this session. `history` is what the journal
+/// window shows, `done` and `undone` are the stacks `:undo` and `:redo` walk.
+#[derive(Default)]
+pub struct Journal {
+    done: Vec<Entry>,
+    undone: Vec<Entry>,
+    history: Vec<String>,
+}
+
+impl Change {
+    pub fn describe(&self) -> String {
+        match self {
+            Change::Copied(from, to) => format!("copied {} to {}", from.display(), to.display()),
+            Change::Moved(from, to) => format!("moved {} to {}", from.display(), to.display()),
+            Change::Trashed(path, _) => format!("trashed {}", path.display()),
+            Change::Restored(path, _) => format!("restored {} from the trash", path.display()),
+            Change::Created(path, true) => format!("created directory {}", path.display()),
+            Change::Created(path, false) => format!("created file {}", path.display()),
+            Change::Removed(path) => format!("deleted {} for good", path.display()),
+            Change::Purged(path) => format!("purged {} from the trash", path.display()),
+        }
+    }
+
+    fn reversible(&self) -> bool {
+        !matches!(self, Change::Removed(_) | Change::Purged(_))
+    }
+
+    /// Copies are trashed rather than deleted, and created entries are only
+    /// removed while they are still empty, so an undo never loses data. An undone
+    /// restore gets a new name in the trash, so the change to redo is returned.
+    fn undo(&self) -> io::Result<Change> {
+        match self {
+            Change::Copied(_, to) => trash::put(to).map(|_| ()),
+            Change::Moved(from, to) => fileops::free(from).and_then(|_| fileops::move_path(to, from)),
+            Change::Trashed(path, name) => trash::restore_to(name, path),
+            Change::Restored(path, _) => return trash::put(path).map(|name| Change::Restored(path.clone(), name)),
+            Change::Created(path, true) => fs::remove_dir(path),
+            Change::Created(path, false) => match fs::metadata(path)?.len() {
+                0 => fs::remove_file(path),
+                _ => Err(io::Error::other("it is no longer empty"))
+            },
+            Change::Removed(_) | Change::Purged(_) => Err(io::Error::other("it was deleted for good")),
+        }.map(|_| self.clone())
+    }
+
+    /// Makes the change again. A trashed entry gets a new name in the trash, so the
+    /// change to record next is returned.
+    fn redo(&self) -> io::Result<Change> {
+        match self {
+            Change::Copied(from, to) => fileops::free(to).and_then(|_| fileops::copy(from, to)),
+            Change::Moved(from, to) => fileops::free(to).and_then(|_| fileops::move_path(from, to)),
+            Change::Trashed(path, _) => return trash::put(path).map(|name| Change::Trashed(path.clone(), name)),
+            Change::Restored(path, name) => trash::restore_to(name, path),
+            Change::Created(path, true) => fs::create_dir(path),
+            Change::Created(path, false) => fileops::free(path).and_then(|_| fileops::touch(path)),
+            Change::Removed(_) | Change::Purged(_) => Err(io::Error::other("it was deleted for good")),
+        }.map(|_| self.clone())
+    }
+}
+
+impl Journal {
+
+    pub fn new() -> Journal {
+        Journal::default()
+    }
+
+    fn log(&mut self, line:String) {
+        self.history.push(format!("{} {}", Local::now().format("%H:%M:%S"), line));
+    }
+
+    /// Records what a file command did. Permanent deletes show up in the history but
+    /// cannot be undone. Any new change drops what was waiting to be redone.
+    pub fn record(&mut self, command:&'static str, changes:Vec<Change>) {
+        if changes.is_empty() {
+            return;
+        }
+        for change in &changes {
+            self.log(format!("{} {}", command, change.describe()));
+        }
+
+        let changes:Vec<Change> = changes.into_iter().filter(Change::reversible).collect();
+        if !changes.is_empty() {
+            self.done.push(Entry { command, changes });
+        }
+        self.undone.clear();
+    }
+
+    /// Takes the last command off the journal for a job to reverse with `undo_entry`.
+    pub fn undo(&mut self) -> Result<Entry, String> {
+        self.done.pop().ok_or(String::from("nothing to undo"))
+    }
+
+    /// Takes the last undone command for a job to make again with `redo_entry`.
+    pub fn redo(&mut self) -> Result<Entry, String> {
+        self.undone.pop().ok_or(String::from("nothing to redo"))
+    }
+
+    /// Puts back what an undo job reversed and what it left. The changes it could not
+    /// reverse stay on the journal so `:undo` can be retried.
+    pub fn undone(&mut self, left:Entry, reversed:Entry) {
+        for change in reversed.changes.iter().rev() {
+            self.log(format!(":undo {} {}", reversed.command, change.describe()));
+        }
+        if !left.changes.is_empty() {
+            self.done.push(left);
+        }
+        if !reversed.changes.is_empty() {
+            self.undone.push(reversed);
+        }
+    }
+
+    /// Puts back what a redo job made again and what it left.
+    pub fn redone(&mut self, left:Entry, redone:Entry) {
+        for change in &redone.changes {
+            self.log(format!(":redo {} {}", redone.command, change.describe()));
+        }
+        if !left.changes.is_empty() {
+            self.undone.push(left);
+        }
+        if !redone.changes.is_empty() {
+            self.done.push(redone);
+        }
+    }
+
+    pub fn history(&self) -> &[String] {
+        &self.history
+    }
+}
+
+impl Entry {
+    pub fn len(&self) -> usize {
+        self.changes.len()
+    }
+}
+
+/// Reverses `entry` newest change first and returns what was reversed. The job
+/// stops at the first change that cannot be reversed, leaving it and the ones
+/// before it in `entry`.
+pub fn undo_entry(entry:&mut Entry, tick:Tick) -> (Entry, Result<(), String>) {
+    let mut reversed = Entry { command: entry.command, changes: Vec::new() };
+    while let Some(change) = entry.changes.pop() {
+        match tick().and_then(|_| change.undo()) {
+            Ok(undone) => reversed.changes.insert(0, undone),
+            Err(e) => {
+                let message = format!(":undo {}: cannot undo `{}`: {}", entry.command, change.describe(), e);
+                entry.changes.push(change);
+                return (reversed, Err(message));
+            }
+        }
+    }
+    (reversed, Ok(()))
+}
+
+/// Makes `entry` again oldest change first and returns what was redone, leaving
+/// what was not in `entry`.
+pub fn redo_entry(entry:&mut Entry, tick:Tick) -> (Entry, Result<(), String>) {
+    let mut redone = Entry { command: entry.command, changes: Vec::new() };
+    while !entry.changes.is_empty() {
+        let change = entry.changes.remove(0);
+        match tick().and_then(|_| change.redo()) {
+            Ok(change) => redone.changes.push(change),
+            Err(e) => {
+                let message = format!(":redo {}: cannot redo `{}`: {}", entry.command, change.describe(), e);
+                entry.changes.insert(0, change);
+                return (redone, Err(message));
+            }
+        }
+    }
+    (redone, Ok(()))
+}
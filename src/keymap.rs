@@ -0,0 +1,180 @@
+use std::collections::BTreeMap;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+/// A single key press, with `Shift` dropped for printable characters since it is
+/// already part of the character.
+pub type Key = (KeyCode, KeyModifiers);
+
+const NAMED_KEYS:&[(&str, KeyCode)] = &[
+    ("left", KeyCode::Left),
+    ("right", KeyCode::Right),
+    ("up", KeyCode::Up),
+    ("down", KeyCode::Down),
+    ("enter", KeyCode::Enter),
+    ("esc", KeyCode::Esc),
+    ("tab", KeyCode::Tab),
+    ("space", KeyCode::Char(' ')),
+    ("backspace", KeyCode::Backspace),
+    ("delete", KeyCode::Delete),
+    ("home", KeyCode::Home),
+    ("end", KeyCode::End),
+    ("pageup", KeyCode::PageUp),
+    ("pagedown", KeyCode::PageDown),
+];
+
+const MODIFIERS:&[(&str, KeyModifiers)] = &[
+    ("c", KeyModifiers::CONTROL),
+    ("ctrl", KeyModifiers::CONTROL),
+    ("a", KeyModifiers::ALT),
+    ("m", KeyModifiers::ALT),
+    ("alt", KeyModifiers::ALT),
+    ("s", KeyModifiers::SHIFT),
+    ("shift", KeyModifiers::SHIFT),
+];
+
+pub fn normalize(key:KeyEvent) -> Key {
+    match key.code {
+        KeyCode::Char(c) => (KeyCode::Char(c), key.modifiers - KeyModifiers::SHIFT),
+        code => (code, key.modifiers)
+    }
+}
+
+/// Parses one key such as `x`, `left`, `C-x` or `Ctrl-Alt-left`.
+fn parse_key(word:&str) -> Option<Key> {
+    let mut pieces:Vec<&str> = word.split('-').collect();
+    // `C--` binds Ctrl and the minus key
+    if word.ends_with("--") {
+        pieces.truncate(pieces.len() - 2);
+        pieces.push("-");
+    }
+    let last = pieces.pop()?;
+
+    let mut modifiers = KeyModifiers::NONE;
+    for piece in pieces {
+        let (_, modifier) = MODIFIERS.iter().find(|(name, _)| name.eq_ignore_ascii_case(piece))?;
+        modifiers |= *modifier;
+    }
+
+    let code = match NAMED_KEYS.iter().find(|(name, _)| name.eq_ignore_ascii_case(last)) {
+        Some((_, code)) => *code,
+        None if last.chars().count() == 1 => KeyCode::Char(last.chars().next()?),
+        None => return None
+    };
+    Some(normalize(KeyEvent::new(code, modifiers)))
+}
+
+/// Parses a key chord. Words separated by spaces are separate presses; a word that
+/// is not a named key or modified key is a run of characters, so `gg` is `g g`.
+pub fn parse_chord(src:&str) -> Result<Vec<Key>, String> {
+    let mut keys = Vec::new();
+
+    for word in src.split_whitespace() {
+        let named = word.contains('-') || NAMED_KEYS.iter().any(|(name, _)| name.eq_ignore_ascii_case(word));
+        if named && word.chars().count() > 1 {
+            keys.push(parse_key(word).ok_or_else(|| format!("unknown key `{}`", word))?);
+            continue;
+        }
+        keys.extend(word.chars().map(|c| (KeyCode::Char(c), KeyModifiers::NONE)));
+    }
+
+    if keys.is_empty() {
+        return Err(String::from("empty key"));
+    }
+    Ok(keys)
+}
+
+fn key_name(key:&Key) -> String {
+    let mut name = String::new();
+    for (prefix, modifier) in [("C-", KeyModifiers::CONTROL), ("A-", KeyModifiers::ALT), ("S-", KeyModifiers::SHIFT)] {
+        if key.1.contains(modifier) {
+            name.push_str(prefix);
+        }
+    }
+
+    match key.0 {
+        KeyCode::Char(' ') => name.push_str("space"),
+        KeyCode::Char(c) => name.push(c),
+        code => match NAMED_KEYS.iter().find(|(_, named)| *named == code) {
+            Some((named, _)) => name.push_str(named),
+            None => name.push_str(&format!("{:?}", code).to_lowercase())
+        }
+    }
+    name
+}
+
+pub fn chord_name(keys:&[Key]) -> String {
+    keys.iter().map(key_name).collect::<Vec<_>>().join(" ")
+}
+
+/// What a key press resolved to.
+#[derive(Debug, PartialEq)]
+pub enum Lookup {
+    /// A binding matched; its fily source should be evaluated.
+    Run(String),
+    /// The keys so far start a longer chord.
+    Pending,
+    /// Nothing is bound, the key goes to the focused element.
+    Unbound,
+}
+
+/// Key bindings, either global or for one kind of element. Element bindings win
+/// over global ones for the same chord.
+#[derive(Debug, Default)]
+pub struct KeyMap {
+    global: Vec<(Vec<Key>, String)>,
+    elements: BTreeMap<String, Vec<(Vec<Key>, String)>>,
+    pending: Vec<Key>,
+}
+
+impl KeyMap {
+    pub fn new() -> KeyMap {
+        KeyMap::default()
+    }
+
+    pub fn bind(&mut self, keys:Vec<Key>, src:String, element:Option<String>) {
+        let table = match element {
+            Some(element) => self.elements.entry(element).or_default(),
+            None => &mut self.global
+        };
+        table.retain(|(bound, _)| *bound != keys);
+        table.push((keys, src));
+    }
+
+    pub fn bindings(&self) -> Vec<(Option<&str>, String, &str)> {
+        let global = self.global.iter().map(|(keys, src)| (None, chord_name(keys), src.as_str()));
+        let elements = self.elements.iter().flat_map(|(element, table)| {
+            table.iter().map(move |(keys, src)| (Some(element.as_str()), chord_name(keys), src.as_str()))
+        });
+        global.chain(elements).collect()
+    }
+
+    /// The keys typed so far of a chord that has not finished yet.
+    pub fn pending(&self) -> Option<String> {
+        if self.pending.is_empty() {None} else {Some(chord_name(&self.pending))}
+    }
+
+    /// Feeds a key press for the focused `element`. An exact match fires right away,
+    /// even when a longer chord shares its prefix.
+    pub fn feed(&mut self, element:&str, key:KeyEvent) -> Lookup {
+        self.pending.push(normalize(key));
+
+        let tables = [self.elements.get(element), Some(&self.global)];
+        let mut prefix = false;
+        for table in tables.into_iter().flatten() {
+            for (keys, src) in table {
+                if *keys == self.pending {
+                    self.pending.clear();
+                    return Lookup::Run(src.clone());
+                }
+                prefix |= keys.starts_with(&self.pending);
+            }
+        }
+
+        if prefix {
+            return Lookup::Pending;
+        }
+        self.pending.clear();
+        Lookup::Unbound
+    }
+}
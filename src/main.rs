@@ -1,12 +1,15 @@
 use crossterm::{
-    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEventKind},
+    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyEventKind},
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
 
-use crate::filyregex::Command;
 use ratatui::{backend::CrosstermBackend, Frame, Terminal};
 use std::io::{self};
+use std::env;
+use std::process;
+use std::path::PathBuf;
+use std::time::Duration;
 
 //changed small thing
 
@@ -17,8 +20,30 @@ mod ui;
 mod filemanager;
 mod filyregex;
 mod empty;
+mod builtins;
+mod batch;
+mod config;
+mod keymap;
+mod fileops;
+mod trash;
+mod trashbin;
+mod journal;
+mod jobs;
+mod jobsview;
+mod bulkrename;
+mod template;
+mod columns;
+mod sorting;
+mod filter;
+mod prompt;
 
 fn main() -> io::Result<()> {
+    // Batch mode never touches the terminal
+    let args:Vec<String> = env::args().skip(1).collect();
+    if !args.is_empty() {
+        process::exit(batch::run(args));
+    }
+
     // Setup the terminal 
     enable_raw_mode()?;
     let mut stdout = io::stdout();
@@ -28,7 +53,8 @@ fn main() -> io::Result<()> {
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
     let mut state:appstate::AppState_t = Box::new(appstate::AppState::new());
-    state.evaluate_commands(vec![Command::Win(String::from("hello"))]);
+    let (config, messages) = config::Config::load();
+    state.startup(config, messages);
 
     // App loop
     let res = run_app(&mut terminal, & mut state);
@@ -36,7 +62,7 @@ fn main() -> io::Result<()> {
     //this should index each file of someFile 1 through 4 (if there are any missing indexes these
     //are ignored then it will cat each file into the variable temp then after the coupled
     //comppands are executed pipe the value of temp into the win terminal
-    let resRegex = filyregex::execute_fily_regex(Some(String::from("./")), String::from("\"value\""));
+    let resRegex = filyregex::execute_fily_regex(Some(PathBuf::from("./")), String::from("\"value\""), &mut filyregex::Environment::new());
    
  //   let mut input = String::new();
     
@@ -44,7 +70,7 @@ fn main() -> io::Result<()> {
         print!("\x1B[2J\x1B[1;1H");
         match io::stdin().read_line(&mut input) {
             Ok(n) => {
-                filyregex::execute_fily_regex(Some(String::from("./")), String::from(input.clone()));
+                filyregex::execute_fily_regex(Some(String::from("./")), String::from(input.clone()), &mut env);
                 input = String::new();
             }
             Err(error) => println!("error: {error}"),
@@ -70,24 +96,19 @@ fn run_app(terminal:&mut Terminal<CrosstermBackend<io::Stdout>>, state:& mut app
             return Ok(());
         }
 
+        // wake up now and then so finished jobs show without a key press
+        state.poll_jobs();
+        if !event::poll(Duration::from_millis(100))? {
+            continue;
+        }
+
         if let Event::Key(key) = event::read()? {
+            if key.kind == KeyEventKind::Press {
+                state.clear_status();
+            }
+
             if key.kind == KeyEventKind::Press && state.windowStates().len() > 0 {
-                let mut commands:Vec<filyregex::Command> = Vec::new();
-                match state.curr_win() {
-                    Some(win) => { 
-                        let res = win.handle_input(key);
-                            
-                        match res {
-                            Some(values) => commands = values,
-                            None => {}
-                        } 
-                    },
-                    None => {continue;}
-                };
-
-                if commands != vec![] {
-                    state.evaluate_commands(commands);
-                }
+                state.handle_key(key);
             }
         }
     }
@@ -0,0 +1,52 @@
+use crate::filyregex::Command;
+
+use crossterm::event::{KeyCode, KeyEvent};
+
+/// The `:` prompt of windows that only list things. `:` opens it, Enter runs what
+/// was typed as a fily expression and Esc drops it.
+#[derive(Clone, Default)]
+pub struct Prompt {
+    isOpen:bool,
+    src:String
+}
+
+impl Prompt {
+
+    pub fn is_open(&self) -> bool {
+        self.isOpen
+    }
+
+    /// What has been typed so far, for the status bar.
+    pub fn text(&self) -> String {
+        self.src.clone()
+    }
+
+    /// Whether `key` is for the prompt: every key while it is open, and `:` to open it.
+    pub fn takes(&self, key:&KeyEvent) -> bool {
+        self.isOpen || key.code == KeyCode::Char(':')
+    }
+
+    pub fn handle_input(&mut self, key:KeyEvent) -> Option<Vec<Command>> {
+        match key.code {
+            KeyCode::Char(':') if !self.isOpen => {
+                self.src.push(':');
+                self.isOpen = true;
+            },
+            KeyCode::Enter => {
+                let src = std::mem::take(&mut self.src);
+                self.isOpen = false;
+                return Some(vec![Command::Eval(src, None)]);
+            },
+            KeyCode::Backspace => {
+                self.src.pop();
+            },
+            KeyCode::Esc => {
+                self.isOpen = false;
+                self.src.clear();
+            },
+            KeyCode::Char(c) => self.src.push(c),
+            _ => {}
+        }
+        None
+    }
+}
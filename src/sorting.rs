@@ -0,0 +1,201 @@
+use std::cmp::Ordering;
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
+
+/// What an explorer orders its entries by.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum SortKey {
+    Name,
+    Size,
+    Modified,
+    Extension,
+    Type,
+}
+
+pub const NAMES:&str = "name, size, mtime, ext or type";
+
+impl SortKey {
+    pub fn parse(name:&str) -> Option<SortKey> {
+        match name {
+            "name" => Some(SortKey::Name),
+            "size" => Some(SortKey::Size),
+            "mtime" => Some(SortKey::Modified),
+            "ext" => Some(SortKey::Extension),
+            "type" => Some(SortKey::Type),
+            _ => None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SortKey::Name => "name",
+            SortKey::Size => "size",
+            SortKey::Modified => "mtime",
+            SortKey::Extension => "ext",
+            SortKey::Type => "type",
+        }
+    }
+
+    /// The key `:sort --next` switches to.
+    fn next(&self) -> SortKey {
+        match self {
+            SortKey::Name => SortKey::Size,
+            SortKey::Size => SortKey::Modified,
+            SortKey::Modified => SortKey::Extension,
+            SortKey::Extension => SortKey::Type,
+            SortKey::Type => SortKey::Name,
+        }
+    }
+}
+
+/// How `:sort` changes the order of an explorer.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Sorting {
+    By(SortKey, bool),
+    Next,
+    Reverse,
+    DirsFirst(bool),
+}
+
+/// The order an explorer lists its entries in.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Sort {
+    key:SortKey,
+    reversed:bool,
+    dirsFirst:bool,
+}
+
+impl Default for Sort {
+    fn default() -> Sort {
+        Sort { key: SortKey::Name, reversed: false, dirsFirst: true }
+    }
+}
+
+impl Sort {
+    pub fn change(&mut self, sorting:Sorting) {
+        match sorting {
+            Sorting::By(key, reversed) => {
+                self.key = key;
+                self.reversed = reversed;
+            },
+            Sorting::Next => self.key = self.key.next(),
+            Sorting::Reverse => self.reversed = !self.reversed,
+            Sorting::DirsFirst(dirsFirst) => self.dirsFirst = dirsFirst,
+        }
+    }
+
+    /// The order for the explorer title, unless it is the default.
+    pub fn describe(&self) -> Option<String> {
+        if *self == Sort::default() {
+            return None;
+        }
+        let mut parts = vec![format!("by {}", self.key.name())];
+        if self.reversed {
+            parts.push(String::from("reversed"));
+        }
+        if !self.dirsFirst {
+            parts.push(String::from("dirs mixed"));
+        }
+        Some(parts.join(", "))
+    }
+
+    /// Sorts `entries` by the metadata and symlink flag `meta` gives for each.
+    pub fn apply<T>(&self, entries:&mut [(PathBuf, T)], meta:impl Fn(&T) -> (Option<&Metadata>, bool)) {
+        entries.sort_by(|(a, aEntry), (b, bEntry)| {
+            let ((aMeta, aLink), (bMeta, bLink)) = (meta(aEntry), meta(bEntry));
+            let isDir = |meta:Option<&Metadata>| meta.is_some_and(Metadata::is_dir);
+            if self.dirsFirst && isDir(aMeta) != isDir(bMeta) {
+                return isDir(bMeta).cmp(&isDir(aMeta));
+            }
+            let order = match self.key {
+                SortKey::Name => Ordering::Equal,
+                SortKey::Size => size(bMeta).cmp(&size(aMeta)),
+                SortKey::Modified => modified(bMeta).cmp(&modified(aMeta)),
+                SortKey::Extension => extension(a).cmp(&extension(b)),
+                SortKey::Type => kind(aMeta, aLink).cmp(&kind(bMeta, bLink)),
+            }.then_with(|| natural(&name(a), &name(b)));
+            if self.reversed {order.reverse()} else {order}
+        });
+    }
+}
+
+fn name(path:&Path) -> String {
+    path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default()
+}
+
+fn size(meta:Option<&Metadata>) -> u64 {
+    meta.map(Metadata::len).unwrap_or(0)
+}
+
+fn modified(meta:Option<&Metadata>) -> Option<std::time::SystemTime> {
+    meta.and_then(|meta| meta.modified().ok())
+}
+
+fn extension(path:&Path) -> String {
+    path.extension().map(|ext| ext.to_string_lossy().to_lowercase()).unwrap_or_default()
+}
+
+/// Directories, then files, then symlinks, then anything else or unreadable.
+fn kind(meta:Option<&Metadata>, isLink:bool) -> u8 {
+    match meta {
+        Some(meta) if meta.is_dir() => 0,
+        Some(meta) if meta.is_file() && !isLink => 1,
+        _ if isLink => 2,
+        _ => 3
+    }
+}
+
+/// Compares runs of digits by their value and the rest ignoring case.
+pub fn natural(a:&str, b:&str) -> Ordering {
+    let (mut left, mut right) = (a.chars().peekable(), b.chars().peekable());
+    loop {
+        let (l, r) = match (left.peek(), right.peek()) {
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(l), Some(r)) => (*l, *r)
+        };
+        let order = if l.is_ascii_digit() && r.is_ascii_digit() {
+            let l = digits(&mut left);
+            let r = digits(&mut right);
+            l.len().cmp(&r.len()).then_with(|| l.cmp(&r))
+        } else {
+            left.next();
+            right.next();
+            l.to_lowercase().cmp(r.to_lowercase())
+        };
+        if order != Ordering::Equal {
+            return order;
+        }
+    }
+}
+
+/// Takes a run of digits without its leading zeros.
+fn digits(chars:&mut std::iter::Peekable<std::str::Chars>) -> String {
+    let mut run = String::new();
+    while let Some(c) = chars.peek().filter(|c| c.is_ascii_digit()) {
+        if !(run.is_empty() && *c == '0') {
+            run.push(*c);
+        }
+        chars.next();
+    }
+    run
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers_sort_by_value() {
+        let mut names = vec!["file10", "file2", "File1", "file02b", "file"];
+        names.sort_by(|a, b| natural(a, b));
+        assert_eq!(names, ["file", "File1", "file2", "file02b", "file10"]);
+    }
+
+    #[test]
+    fn ties_fall_back_to_the_exact_name() {
+        assert_eq!(natural("a", "A"), "a".cmp("A"));
+        assert_eq!(natural("a01", "a1"), "a01".cmp("a1"));
+    }
+}
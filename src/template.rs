@@ -0,0 +1,275 @@
+use std::path::{Path, PathBuf};
+use regex::{Captures, Regex};
+
+/// A name template for `:rename`, e.g. `{stem:lower}_{n:03}.{ext}`. The fields are
+/// `name`, `stem`, `ext`, the counter `n` and `0`, `1`, ... for the groups captured by
+/// the pattern. Text fields take `:upper`, `:lower` or `:title`, the counter takes a
+/// width such as `:3` or `:03`, up to 255. `{{` and `}}` stand for literal braces.
+#[derive(Debug, Clone)]
+pub struct Template {
+    src:String,
+    parts:Vec<Part>,
+    pattern:Option<Regex>,
+}
+
+#[derive(Debug, Clone)]
+enum Part {
+    Text(String),
+    Field(Field, Format),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Field {
+    Name,
+    Stem,
+    Ext,
+    Counter,
+    Group(usize),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Format {
+    Plain,
+    Upper,
+    Lower,
+    Title,
+    Width(usize, bool),
+}
+
+impl PartialEq for Template {
+    fn eq(&self, other:&Template) -> bool {
+        self.src == other.src && self.pattern.as_ref().map(Regex::as_str) == other.pattern.as_ref().map(Regex::as_str)
+    }
+}
+
+/// Compiles the pattern a template's groups are captured from.
+pub fn pattern(src:&str) -> Result<Regex, String> {
+    // the full error draws its own caret over several lines, fily draws one already
+    Regex::new(src).map_err(|e| {
+        let reason = e.to_string().lines().last().unwrap_or_default().trim_start_matches("error: ").to_string();
+        format!("`{}` is not a valid pattern: {}", src, reason)
+    })
+}
+
+impl Template {
+
+    /// Whether `:rename` treats `name` as a template rather than a plain new name.
+    pub fn is_template(name:&str) -> bool {
+        name.contains('{') || name.contains('}')
+    }
+
+    pub fn parse(src:&str, pattern:Option<Regex>) -> Result<Template, String> {
+        let groups = pattern.as_ref().map(Regex::captures_len).unwrap_or(0);
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut chars = src.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                },
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                },
+                '}' => return Err(String::from("unmatched `}`, write `}}` for a literal brace")),
+                '{' => {
+                    let mut field = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => field.push(c),
+                            None => return Err(format!("unclosed `{{{}`", field))
+                        }
+                    }
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(parse_field(&field, groups)?);
+                },
+                c => text.push(c)
+            }
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        Ok(Template { src: String::from(src), parts, pattern })
+    }
+
+    /// The new name of every path, with the counter going up from 1 in order. Names
+    /// the pattern does not match are `None`, so they are kept as they are, and are
+    /// not counted.
+    pub fn apply(&self, paths:&[PathBuf]) -> Result<Vec<Option<String>>, String> {
+        let mut n = 0;
+        let mut names = Vec::new();
+        for path in paths {
+            let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+            let captures = match &self.pattern {
+                Some(pattern) => match pattern.captures(&name) {
+                    Some(captures) => Some(captures),
+                    None => {
+                        names.push(None);
+                        continue;
+                    }
+                },
+                None => None
+            };
+            n += 1;
+
+            let renamed = self.render(path, &name, n, captures.as_ref());
+            if renamed.is_empty() || renamed == "." || renamed == ".." || renamed.contains('/') {
+                return Err(format!("`{}` would become `{}`, which is not a file name", name, renamed));
+            }
+            names.push(Some(renamed));
+        }
+        Ok(names)
+    }
+
+    fn render(&self, path:&Path, name:&str, n:usize, captures:Option<&Captures>) -> String {
+        let mut out = String::new();
+        for part in &self.parts {
+            let (field, format) = match part {
+                Part::Text(text) => {
+                    out.push_str(text);
+                    continue;
+                },
+                Part::Field(field, format) => (*field, *format)
+            };
+            let value = match field {
+                Field::Name => String::from(name),
+                Field::Stem => path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default(),
+                Field::Ext => path.extension().map(|ext| ext.to_string_lossy().into_owned()).unwrap_or_default(),
+                Field::Counter => n.to_string(),
+                Field::Group(i) => captures.and_then(|captures| captures.get(i)).map(|group| String::from(group.as_str())).unwrap_or_default()
+            };
+            match format {
+                Format::Plain => out.push_str(&value),
+                Format::Upper => out.push_str(&value.to_uppercase()),
+                Format::Lower => out.push_str(&value.to_lowercase()),
+                Format::Title => out.push_str(&title_case(&value)),
+                Format::Width(width, true) => out.push_str(&format!("{:0>width$}", value, width = width)),
+                Format::Width(width, false) => out.push_str(&format!("{:>width$}", value, width = width)),
+            }
+        }
+        out
+    }
+}
+
+/// The widest a counter can be padded, names are at most 255 bytes anyway.
+const MAX_WIDTH:usize = 255;
+
+fn parse_field(src:&str, groups:usize) -> Result<Part, String> {
+    let (name, spec) = match src.split_once(':') {
+        Some((name, spec)) => (name, Some(spec)),
+        None => (src, None)
+    };
+    let field = match name {
+        "name" => Field::Name,
+        "stem" => Field::Stem,
+        "ext" => Field::Ext,
+        "n" => Field::Counter,
+        group if !group.is_empty() && group.chars().all(|c| c.is_ascii_digit()) => {
+            let i = group.parse::<usize>().map_err(|_| format!("`{{{}}}` is not a group", src))?;
+            if groups == 0 {
+                return Err(format!("`{{{}}}` needs a pattern to capture from, e.g. `:rename \"{{1}}.jpg\" \"IMG_(.*)\\.jpeg\"`", src));
+            }
+            if i >= groups {
+                return Err(format!("`{{{}}}` is past the last group of the pattern", src));
+            }
+            Field::Group(i)
+        },
+        _ => return Err(format!("unknown field `{{{}}}`, expected name, stem, ext, n or a group number", src))
+    };
+
+    let format = match (field, spec) {
+        (_, None) => Format::Plain,
+        (Field::Counter, Some(width)) => match width.parse::<usize>() {
+            Ok(n) if n <= MAX_WIDTH => Format::Width(n, width.starts_with('0')),
+            Ok(_) => return Err(format!("`{{{}}}` is wider than {}", src, MAX_WIDTH)),
+            Err(_) => return Err(format!("`{{{}}}` takes a width such as `{{n:03}}`", src))
+        },
+        (_, Some("upper")) => Format::Upper,
+        (_, Some("lower")) => Format::Lower,
+        (_, Some("title")) => Format::Title,
+        (_, Some(_)) => return Err(format!("`{{{}}}` takes upper, lower or title", src))
+    };
+    Ok(Part::Field(field, format))
+}
+
+/// Upper-cases the first letter of every word and lower-cases the rest.
+fn title_case(text:&str) -> String {
+    let mut out = String::new();
+    let mut start = true;
+    for c in text.chars() {
+        if start {
+            out.extend(c.to_uppercase());
+        } else {
+            out.extend(c.to_lowercase());
+        }
+        start = !c.is_alphanumeric();
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(names:&[&str]) -> Vec<PathBuf> {
+        names.iter().map(|name| Path::new("/tmp").join(name)).collect()
+    }
+
+    fn rename(src:&str, pattern:Option<&str>, names:&[&str]) -> Result<Vec<Option<String>>, String> {
+        let pattern = pattern.map(|src| super::pattern(src).unwrap());
+        Template::parse(src, pattern)?.apply(&paths(names))
+    }
+
+    #[test]
+    fn fields_and_counter() {
+        let names = rename("{stem}_{n:03}.{ext}", None, &["a.jpeg", "b.png"]).unwrap();
+        assert_eq!(names, [Some(String::from("a_001.jpeg")), Some(String::from("b_002.png"))]);
+        let names = rename("{n:3}-{name}", None, &["x"]).unwrap();
+        assert_eq!(names, [Some(String::from("  1-x"))]);
+    }
+
+    #[test]
+    fn case_changes() {
+        let names = rename("{stem:upper}.{ext:lower}", None, &["Photo one.JPG"]).unwrap();
+        assert_eq!(names, [Some(String::from("PHOTO ONE.jpg"))]);
+        let names = rename("{stem:title}", None, &["hELLO wORLD-again"]).unwrap();
+        assert_eq!(names, [Some(String::from("Hello World-Again"))]);
+    }
+
+    #[test]
+    fn escaped_braces() {
+        let names = rename("{{{stem}}}", None, &["a.txt"]).unwrap();
+        assert_eq!(names, [Some(String::from("{a}"))]);
+    }
+
+    #[test]
+    fn groups_skip_what_the_pattern_misses() {
+        let names = rename("{1}_{n}.jpg", Some(r"IMG_(\d+)"), &["IMG_12.jpeg", "notes.txt", "IMG_7.jpeg"]).unwrap();
+        assert_eq!(names, [Some(String::from("12_1.jpg")), None, Some(String::from("7_2.jpg"))]);
+    }
+
+    #[test]
+    fn bad_templates_are_refused() {
+        assert!(Template::parse("{stem", None).is_err());
+        assert!(Template::parse("stem}", None).is_err());
+        assert!(Template::parse("{size}", None).is_err());
+        assert!(Template::parse("{1}", None).is_err());
+        assert!(Template::parse("{2}", Some(pattern("(a)").unwrap())).is_err());
+        assert!(Template::parse("{n:upper}", None).is_err());
+        assert!(Template::parse("{name:3}", None).is_err());
+        assert!(Template::parse("{n:999999999999}", None).is_err());
+        assert!(Template::parse("{n:255}", None).is_ok());
+    }
+
+    #[test]
+    fn names_must_stay_names() {
+        assert!(rename("{stem}/x", None, &["a"]).is_err());
+        assert!(rename("{1}", Some("(b)?a"), &["a"]).is_err());
+    }
+}
@@ -0,0 +1,189 @@
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{self, Path, PathBuf};
+use chrono::Local;
+use crate::fileops;
+
+/// `$XDG_DATA_HOME/Trash`, falling back to `~/.local/share/Trash`.
+pub fn home_trash() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_DATA_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".local").join("share")
+    };
+    Some(base.join("Trash"))
+}
+
+/// An entry of the trash as described by its `info/<name>.trashinfo` file.
+#[derive(Debug, Clone)]
+pub struct TrashItem {
+    pub name: String,
+    pub original: PathBuf,
+    pub deleted: String,
+}
+
+fn dirs() -> io::Result<(PathBuf, PathBuf)> {
+    let trash = home_trash().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "there is no home directory to keep a trash in"))?;
+    Ok((trash.join("files"), trash.join("info")))
+}
+
+fn info_path(info:&Path, name:&str) -> PathBuf {
+    info.join(format!("{}.trashinfo", name))
+}
+
+/// Moves `path` into the trash and returns its name there. The name is claimed by
+/// creating its `.trashinfo` exclusively first, as the spec asks, so two fily
+/// instances never collide.
+pub fn put(path:&Path) -> io::Result<String> {
+    let (files, info) = dirs()?;
+    fs::create_dir_all(&files)?;
+    fs::create_dir_all(&info)?;
+
+    let original = path::absolute(path)?;
+    let base = match original.file_name() {
+        Some(base) => base.to_string_lossy().into_owned(),
+        None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "cannot trash a root directory"))
+    };
+
+    let mut n = 0;
+    loop {
+        n += 1;
+        let name = if n == 1 {base.clone()} else {format!("{}.{}", base, n)};
+        let mut file = match fs::OpenOptions::new().write(true).create_new(true).open(info_path(&info, &name)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e)
+        };
+
+        // a stray file without its info must not be overwritten either
+        let res = if files.join(&name).symlink_metadata().is_ok() {
+            Err(io::Error::from(io::ErrorKind::AlreadyExists))
+        } else {
+            write!(file, "[Trash Info]\nPath={}\nDeletionDate={}\n", encode(&original), Local::now().format("%Y-%m-%dT%H:%M:%S"))
+                .and_then(|_| fileops::move_path(path, &files.join(&name)))
+        };
+        match res {
+            Ok(()) => return Ok(name),
+            Err(e) => {
+                let _ = fs::remove_file(info_path(&info, &name));
+                if e.kind() != io::ErrorKind::AlreadyExists {
+                    return Err(e);
+                }
+            }
+        }
+    }
+}
+
+/// Every readable entry of the trash, most recently deleted first.
+pub fn list() -> io::Result<Vec<TrashItem>> {
+    let (_, info) = dirs()?;
+    let entries = match fs::read_dir(&info) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e)
+    };
+
+    let mut items:Vec<TrashItem> = entries.filter_map(|entry| {
+        let entry = entry.ok()?;
+        let file_name = entry.file_name();
+        let name = file_name.to_str()?.strip_suffix(".trashinfo")?;
+        parse_info(name, &fs::read_to_string(entry.path()).ok()?)
+    }).collect();
+    items.sort_by(|a, b| b.deleted.cmp(&a.deleted).then_with(|| a.name.cmp(&b.name)));
+    Ok(items)
+}
+
+fn parse_info(name:&str, text:&str) -> Option<TrashItem> {
+    let mut lines = text.lines().map(str::trim).filter(|line| !line.is_empty());
+    if lines.next()? != "[Trash Info]" {
+        return None;
+    }
+
+    let (mut original, mut deleted) = (None, String::new());
+    for line in lines {
+        match line.split_once('=') {
+            Some(("Path", value)) => original = Some(decode(value)),
+            Some(("DeletionDate", value)) => deleted = String::from(value),
+            _ => {}
+        }
+    }
+    Some(TrashItem { name: String::from(name), original: original?, deleted })
+}
+
+/// Moves an item back to where it was deleted from, recreating missing parents.
+pub fn restore(item:&TrashItem) -> io::Result<()> {
+    restore_to(&item.name, &item.original)
+}
+
+pub fn restore_to(name:&str, original:&Path) -> io::Result<()> {
+    let (files, info) = dirs()?;
+    fileops::free(original)?;
+    if let Some(parent) = original.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fileops::move_path(&files.join(name), original)?;
+    fs::remove_file(info_path(&info, name))
+}
+
+/// Deletes an item for good.
+pub fn purge(item:&TrashItem) -> io::Result<()> {
+    let (files, info) = dirs()?;
+    match fileops::remove(&files.join(&item.name)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    fs::remove_file(info_path(&info, &item.name))
+}
+
+/// Percent-encodes a path for the `Path=` key, leaving `/` and unreserved bytes as is.
+fn encode(path:&Path) -> String {
+    path_bytes(path).iter().map(|&b| match b {
+        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => String::from(b as char),
+        b => format!("%{:02X}", b)
+    }).collect()
+}
+
+fn decode(value:&str) -> PathBuf {
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(b)) => {
+                out.push(b);
+                i += 3;
+            },
+            (b, _) => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    path_from_bytes(out)
+}
+
+#[cfg(unix)]
+fn path_bytes(path:&Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes().to_vec()
+}
+
+#[cfg(not(unix))]
+fn path_bytes(path:&Path) -> Vec<u8> {
+    path.to_string_lossy().into_owned().into_bytes()
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes:Vec<u8>) -> PathBuf {
+    use std::ffi::OsString;
+    use std::os::unix::ffi::OsStringExt;
+    PathBuf::from(OsString::from_vec(bytes))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes:Vec<u8>) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(&bytes).into_owned())
+}
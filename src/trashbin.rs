@@ -0,0 +1,146 @@
+use crate::appstate::AppState;
+use crate::fileops::{Change, Tick};
+use crate::filyregex::Command;
+use crate::prompt::Prompt;
+use crate::trash;
+use crate::trash::TrashItem;
+
+use crossterm::event::{KeyCode, KeyEvent};
+
+use ratatui::{
+    layout::{Alignment, Constraint, Direction, Layout, Rect},
+    style::{Color, Style},
+    widgets::{Block, BorderType, Borders, Paragraph},
+    Frame,
+};
+
+/// Lists the home trash so deleted entries can be restored or purged.
+#[derive(Clone)]
+pub struct TrashBin {
+    items:Vec<TrashItem>,
+    itemIndex:usize,
+    error:Option<String>,
+    prompt:Prompt
+}
+
+impl TrashBin {
+
+    pub fn new() -> TrashBin {
+        let mut bin = TrashBin {
+            items: Vec::new(),
+            itemIndex: 0,
+            error: None,
+            prompt: Prompt::default()
+        };
+        bin.refresh();
+        bin
+    }
+
+    pub fn refresh(&mut self) {
+        match trash::list() {
+            Ok(items) => {
+                self.items = items;
+                self.error = None;
+            },
+            Err(e) => self.error = Some(format!("cannot read the trash: {}", e))
+        }
+        self.itemIndex = self.itemIndex.min(self.items.len().saturating_sub(1));
+    }
+
+    pub fn searching(&self) -> bool {
+        self.prompt.is_open()
+    }
+
+    pub fn pulling_info(&self) -> String {
+        self.prompt.text()
+    }
+
+    /// Puts the item under the cursor back where it came from.
+    pub fn restore(&mut self) -> (Vec<Change>, Result<(), String>) {
+        let item = match self.items.get(self.itemIndex) {
+            Some(item) => item,
+            None => return (Vec::new(), Err(String::from("the trash is empty")))
+        };
+        match trash::restore(item) {
+            Ok(()) => (vec![Change::Restored(item.original.clone(), item.name.clone())], Ok(())),
+            Err(e) => (Vec::new(), Err(format!(":restore: {}: {}", item.original.display(), e)))
+        }
+    }
+
+    /// The item under the cursor, or everything with `all`, for a job to purge.
+    pub fn to_purge(&self, all:bool) -> Result<Vec<TrashItem>, String> {
+        let items = if all {&self.items[..]} else {self.items.get(self.itemIndex..=self.itemIndex).unwrap_or(&[])};
+        if items.is_empty() {
+            return Err(String::from("the trash is empty"));
+        }
+        Ok(items.to_vec())
+    }
+
+    pub fn handle_input(&mut self, key:KeyEvent) -> Option<Vec<Command>> {
+        if self.prompt.takes(&key) {
+            return self.prompt.handle_input(key);
+        }
+        match key.code {
+            KeyCode::Up => {
+                self.itemIndex = self.itemIndex.saturating_sub(1);
+            },
+            KeyCode::Down if self.itemIndex + 1 < self.items.len() => {
+                self.itemIndex += 1;
+            },
+            _ => {}
+        }
+        None
+    }
+
+    pub fn render(&self, f: &mut Frame, _appState:&AppState, outter:Rect, isFocused: bool) {
+        f.render_widget(
+        Block::new()
+            .border_type(BorderType::Rounded)
+            .borders(Borders::ALL)
+            .border_style(Style::default().fg(if isFocused {Color::Blue} else {Color::White}))
+            .title(format!("trash [{} items]", self.items.len())),
+        outter);
+
+        let inner = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints(vec![Constraint::Percentage(2), Constraint::Percentage(98)])
+            .split(outter)[1];
+        let rows = (inner.height as usize).saturating_sub(2);
+        let bounds = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(vec![Constraint::Length(1); rows + 1])
+            .split(inner);
+
+        if let Some(error) = &self.error {
+            f.render_widget(Paragraph::new(error.clone()).style(Style::default().fg(Color::Red)), bounds[1]);
+            return;
+        }
+
+        let start = (self.itemIndex + 1).saturating_sub(rows);
+        for (row, i) in (start..self.items.len()).take(rows).enumerate() {
+            let item = &self.items[i];
+            let style = if i == self.itemIndex {
+                Style::default().bg(Color::Blue).fg(Color::Red)
+            } else {
+                Style::default().fg(Color::White)
+            };
+            let p = Paragraph::new(format!("{}  ({})", item.original.display(), item.deleted.replace('T', " ")))
+                .style(style)
+                .alignment(Alignment::Center);
+            f.render_widget(p, bounds[row + 1]);
+        }
+    }
+
+}
+
+/// Deletes `items` for good. The items purged before a failure are returned with it.
+pub fn purge_with(items:&[TrashItem], tick:Tick) -> (Vec<Change>, Result<(), String>) {
+    let mut changes = Vec::new();
+    for item in items {
+        if let Err(e) = tick().and_then(|_| trash::purge(item)) {
+            return (changes, Err(format!(":purge: {}: {}", item.original.display(), e)));
+        }
+        changes.push(Change::Purged(item.original.clone()));
+    }
+    (changes, Ok(()))
+}
@@ -1,5 +1,5 @@
-use glob::glob;
 use crate::appstate;
+use crate::filyregex::FilyError;
 use crate::window::Element;
 use std::fs;
 use std::path::Path;
@@ -8,7 +8,7 @@ use ratatui::{
     layout::{Alignment, Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
     text::{Span, Line},
-    widgets::{Block, BorderType, Borders, Paragraph},
+    widgets::{Block, BorderType, Borders, Clear, Paragraph, Wrap},
     Frame,
 };
 
@@ -17,12 +17,13 @@ use ratatui::{
 pub fn ui_render(f: &mut Frame, state:&mut appstate::AppState_t) {
     let size = f.size();
     
+    let statusHeight = if state.status().is_some() {3} else {1};
     let outterLayout = Layout::default()
         .direction(Direction::Vertical)
         .constraints(vec! [
             Constraint::Percentage(2),
-            Constraint::Percentage(96),
-            Constraint::Percentage(2),
+            Constraint::Min(0),
+            Constraint::Length(statusHeight),
         ]).split(f.size());
 
     let mut innerConstraints = vec![];
@@ -56,14 +57,32 @@ pub fn ui_render(f: &mut Frame, state:&mut appstate::AppState_t) {
         .constraints(vec![Constraint::Percentage(5),Constraint::Percentage(95)])
         .split(outterLayout[0]);
 
-    let windowState = state.curr_win().unwrap();
-    
-    
-    if  !windowState.using_keyboard() {
-        f.render_widget(Span::styled(format!("{}, window count {}", windowState.name(), state.windowStates().len()), Style::default().bg(Color::Blue).fg(Color::Black)), outterLayout[2]); 
-    } else {
-        f.render_widget(Span::styled(format!("#{}", windowState.pulling_info()), Style::default().bg(Color::Red).fg(Color::Black)), outterLayout[2]); 
-    }   
+ 
+    if let Some(e) = state.status() {
+        render_error(f, e, outterLayout[2]);
+    } else if let Some(keys) = state.pending_keys() {
+        f.render_widget(Span::styled(format!("{} ...", keys), Style::default().bg(Color::Yellow).fg(Color::Black)), outterLayout[2]); 
+    } else if let Some(windowState) = state.curr_win() {
+        if  !windowState.using_keyboard() {
+            let listingError = windowState.listing_error();
+            let mut info = vec![Span::styled(format!("{}, window count {}", windowState.name(), state.windowStates().len()), Style::default().bg(Color::Blue).fg(Color::Black))];
+            if let Some(error) = listingError {
+                info.push(Span::raw(" "));
+                info.push(Span::styled(error, Style::default().bg(Color::Red).fg(Color::Black)));
+            }
+            if let Some(job) = state.jobs_info() {
+                info.push(Span::raw(" "));
+                info.push(Span::styled(format!("job: {}", job), Style::default().bg(Color::Yellow).fg(Color::Black)));
+            }
+            if let Some(clip) = state.clipboard_info() {
+                info.push(Span::raw(" "));
+                info.push(Span::styled(format!("clip: {}", clip), Style::default().bg(Color::Green).fg(Color::Black)));
+            }
+            f.render_widget(Line::from(info), outterLayout[2]); 
+        } else {
+            f.render_widget(Span::styled(format!("#{}", windowState.pulling_info()), Style::default().bg(Color::Red).fg(Color::Black)), outterLayout[2]); 
+        }   
+    }
     let windowIsPulling = false;
     
     for i in 0..state.windowStates().len(){
@@ -75,8 +94,43 @@ pub fn ui_render(f: &mut Frame, state:&mut appstate::AppState_t) {
              ]).split(innerLayout[i]);
         state.windowStates()[i].render(f, &state, filesOutter[1], i == state.curr_win_index()); 
     }
+
+    if let Some((from, to)) = state.conflict() {
+        render_conflict(f, from, to, size);
+    }
     
 
 
 }
 
+/// Draws a fily error as its message, the offending source and a caret under the span.
+fn render_error(f: &mut Frame, e: &FilyError, area: Rect) {
+    let mut lines = vec![Line::from(Span::styled(e.to_string(), Style::default().bg(Color::Red).fg(Color::Black)))];
+
+    if let Some((src, caret)) = e.caret() {
+        lines.push(Line::from(src));
+        lines.push(Line::from(Span::styled(caret, Style::default().fg(Color::Red).add_modifier(Modifier::BOLD))));
+    }
+
+    f.render_widget(Paragraph::new(lines), area);
+}
+
+/// Draws the prompt for a job waiting on a destination that already exists, centered
+/// over the windows.
+fn render_conflict(f: &mut Frame, from: &Path, to: &Path, size: Rect) {
+    let width = size.width.min(70);
+    let height = size.height.min(8);
+    let area = Rect::new(size.x + (size.width - width) / 2, size.y + (size.height - height) / 2, width, height);
+
+    let lines = vec![
+        Line::from(format!("`{}` already exists", to.display())),
+        Line::from(format!("replace it with `{}`?", from.display())),
+        Line::from(""),
+        Line::from(Span::styled("o overwrite  s skip  r rename  c cancel", Style::default().add_modifier(Modifier::BOLD))),
+        Line::from("O, S or R do the same for the rest of the job"),
+    ];
+    f.render_widget(Clear, area);
+    f.render_widget(Paragraph::new(lines)
+        .wrap(Wrap { trim: false })
+        .block(Block::bordered().border_type(BorderType::Rounded).title("conflict").style(Style::default().fg(Color::Yellow))), area);
+}
@@ -1,5 +1,6 @@
 
 use std::path;
+use std::path::PathBuf;
 use crate::filyregex::Command;
 
 use ratatui::{
@@ -17,14 +18,23 @@ use crossterm::{
 
 use crossterm::event::KeyEvent;
 use crate::filemanager;
+use crate::filemanager::Marking;
+use crate::sorting::Sorting;
+use crate::filter::{Filter, Filtering};
 use crate::appstate::AppState;
 use crate::empty;
+use crate::trashbin;
+use crate::jobsview;
+use crate::bulkrename;
 
 
 
 pub enum Element{
     FileManager(Box<filemanager::FileManager>),
     Empty(Box<empty::Empty>),
+    Trash(Box<trashbin::TrashBin>),
+    Jobs(Box<jobsview::JobsView>),
+    BulkRename(Box<bulkrename::BulkRename>),
 //    TextManager(Vec<String>),
     None,
 }
@@ -46,6 +56,9 @@ impl Clone for Element {
         match &self {
             Element::FileManager(fm) => Element::FileManager(fm.clone()),
             Element::Empty(contents) => Element::Empty(contents.clone()),
+            Element::Trash(bin) => Element::Trash(bin.clone()),
+            Element::Jobs(jobs) => Element::Jobs(jobs.clone()),
+            Element::BulkRename(bulk) => Element::BulkRename(bulk.clone()),
  //           Element::TextManager(tm) => Element::TextManager(tm),
             Element::None => Element::None,
         
@@ -65,6 +78,123 @@ impl WindowState {
         return self.windowName.clone();
     }
 
+    pub fn set_name(&mut self, name:String) {
+        self.windowName = name;
+    }
+
+    /// The name used for this element in `@CORE_<Element>` sections and `:map --in`.
+    pub fn element_name(&self) -> &'static str {
+        match &self.elements {
+            Element::FileManager(_) => "FileManager",
+            Element::Empty(_) => "Empty",
+            Element::Trash(_) => "Trash",
+            Element::Jobs(_) => "Jobs",
+            Element::BulkRename(_) => "BulkRename",
+            Element::None => "None",
+        }
+    }
+
+    /// The directory fily expressions typed in this window are resolved against.
+    pub fn curr_dir(&self) -> Option<PathBuf> {
+        match &self.elements {
+            Element::FileManager(fm) => Some(fm.curr_dir()),
+            _ => None
+        }
+    }
+
+    /// What file commands act on when no paths are piped in.
+    pub fn targets(&self) -> Vec<PathBuf> {
+        match &self.elements {
+            Element::FileManager(fm) => fm.targets(),
+            _ => Vec::new()
+        }
+    }
+
+    /// Where `:paste` puts the clipboard.
+    pub fn paste_dir(&self) -> Option<PathBuf> {
+        match &self.elements {
+            Element::FileManager(fm) => Some(fm.listed_dir()),
+            _ => None
+        }
+    }
+
+    /// Why the listing is empty or incomplete, for the status bar.
+    pub fn listing_error(&self) -> Option<String> {
+        match &self.elements {
+            Element::FileManager(fm) => fm.error().map(String::from),
+            _ => None
+        }
+    }
+
+    pub fn marked(&self) -> Vec<PathBuf> {
+        match &self.elements {
+            Element::FileManager(fm) => fm.marked(),
+            _ => Vec::new()
+        }
+    }
+
+    /// Every entry the window lists.
+    pub fn listed(&self) -> Vec<PathBuf> {
+        match &self.elements {
+            Element::FileManager(fm) => fm.listed(),
+            _ => Vec::new()
+        }
+    }
+
+    /// Returns false when the window has nothing to mark.
+    pub fn mark(&mut self, marking:Marking) -> bool {
+        match &mut self.elements {
+            Element::FileManager(fm) => {
+                fm.mark(marking);
+                true
+            },
+            _ => false
+        }
+    }
+
+    pub fn sort(&mut self, sorting:Sorting) -> bool {
+        match &mut self.elements {
+            Element::FileManager(fm) => {
+                fm.sort(sorting);
+                true
+            },
+            _ => false
+        }
+    }
+
+    pub fn filter(&mut self, filtering:Filtering) -> bool {
+        match &mut self.elements {
+            Element::FileManager(fm) => {
+                fm.filter(filtering);
+                true
+            },
+            _ => false
+        }
+    }
+
+    /// What globs typed in this window leave out.
+    pub fn entry_filter(&self) -> Filter {
+        match &self.elements {
+            Element::FileManager(fm) => fm.entry_filter(),
+            _ => Filter::default()
+        }
+    }
+
+    pub fn refresh(&mut self) {
+        match &mut self.elements {
+            Element::FileManager(fm) => fm.refresh(),
+            Element::Trash(bin) => bin.refresh(),
+            _ => {}
+        }
+    }
+
+    pub fn trash_bin(&mut self) -> Option<&mut trashbin::TrashBin> {
+        match &mut self.elements {
+            Element::Trash(bin) => Some(bin),
+            _ => None
+        }
+    }
+
     pub fn elements(&self) -> &Element {
         return &self.elements;
     }
@@ -77,6 +207,9 @@ impl WindowState {
             Element::Empty(empty) => {
                 empty.searching()
             }
+            Element::Trash(bin) => bin.searching(),
+            Element::Jobs(jobs) => jobs.searching(),
+            Element::BulkRename(bulk) => bulk.searching(),
             Element::None => {false}
         };
     }
@@ -89,6 +222,9 @@ impl WindowState {
             Element::Empty(empty) => {
                 empty.pulling_info()
             }
+            Element::Trash(bin) => bin.pulling_info(),
+            Element::Jobs(jobs) => jobs.pulling_info(),
+            Element::BulkRename(bulk) => bulk.pulling_info(),
             Element::None => String::from(""),
         }
     }
@@ -104,6 +240,9 @@ impl WindowState {
         return match &self.elements {
             Element::FileManager(fm) => fm.searching(),
             Element::Empty(empty) => empty.searching(),
+            Element::Trash(bin) => bin.searching(),
+            Element::Jobs(jobs) => jobs.searching(),
+            Element::BulkRename(bulk) => bulk.searching(),
             Element::None => false
         }
     }
@@ -115,6 +254,9 @@ impl WindowState {
             Element::Empty(emp) => {
                 emp.handle_input(key)
             }
+            Element::Trash(bin) => bin.handle_input(key),
+            Element::Jobs(jobs) => jobs.handle_input(key),
+            Element::BulkRename(bulk) => bulk.handle_input(key),
   //          Element::TextManager(text) => {},
             Element::None => {None}
         }
@@ -125,6 +267,9 @@ impl WindowState {
         match &self.elements {
             Element::FileManager(fm) => {fm.render(f, appState, outter, isFocused)},
             Element::Empty(emp) => {emp.render(f,appState,outter,isFocused)},
+            Element::Trash(bin) => {bin.render(f,appState,outter,isFocused)},
+            Element::Jobs(jobs) => {jobs.render(f,appState,outter,isFocused)},
+            Element::BulkRename(bulk) => {bulk.render(f,appState,outter,isFocused)},
             Element::None => {}
         };  
     }